@@ -0,0 +1,164 @@
+//! Axis aligned bounding boxes
+
+extern crate cgmath;
+
+use super::rays;
+use cgmath::*;
+
+/// A box with sides parallel to the axes, given by its two opposite corners
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// The box between two corners, in any order
+    pub fn new(a: Point3<f32>, b: Point3<f32>) -> Aabb {
+        Aabb {
+            min: Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }
+
+    /// A box that contains nothing, and is the identity for `union`
+    pub fn empty() -> Aabb {
+        let inf = f32::INFINITY;
+        Aabb {
+            min: Point3::new(inf, inf, inf),
+            max: Point3::new(-inf, -inf, -inf),
+        }
+    }
+
+    /// The smallest box around a set of points
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Aabb {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |aabb, point| aabb.grow(point))
+    }
+
+    /// Returns true if the box contains nothing
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Returns true if the point is inside the box or on its surface
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        (0..3).all(|axis| self.min[axis] <= point[axis] && point[axis] <= self.max[axis])
+    }
+
+    /// Returns true if the boxes have any point in common
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    /// The smallest box containing both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    /// The box where the boxes overlap, which is empty if they do not
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.max(other.min.x),
+                self.min.y.max(other.min.y),
+                self.min.z.max(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.min(other.max.x),
+                self.max.y.min(other.max.y),
+                self.max.z.min(other.max.z),
+            ),
+        }
+    }
+
+    /// The smallest box containing the box and a point
+    pub fn grow(&self, point: Point3<f32>) -> Aabb {
+        self.union(&Aabb {
+            min: point,
+            max: point,
+        })
+    }
+
+    /// The point in the middle of the box
+    pub fn centroid(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// The length of the sides of the box
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// The area of the surface of the box, zero if it is empty
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let size = self.size();
+        2. * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// The eight corners of the box
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// The distances along the ray where it enters and exits the box, using the slab method.
+    /// `inv_direction` is one divided by each component of the direction of the ray.
+    /// The entry may be negative if the ray starts inside the box. An empty box is never hit.
+    pub fn slab(&self, origin: Point3<f32>, inv_direction: Vector3<f32>) -> Option<(f32, f32)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut near = -f32::INFINITY;
+        let mut far = f32::INFINITY;
+
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inv_direction[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inv_direction[axis];
+
+            // NaN happens when the ray is parallel to the slab and starts on its edge, and is
+            // ignored by min and max
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+
+        if near <= far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the ray hits the box somewhere between its origin and `t_max`
+    pub fn hit(&self, ray: &rays::Ray, inv_direction: Vector3<f32>, t_max: f32) -> bool {
+        match self.slab(ray.origin, inv_direction) {
+            Some((near, far)) => far >= 0. && near <= t_max,
+            None => false,
+        }
+    }
+}
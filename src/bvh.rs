@@ -0,0 +1,270 @@
+//! A bounding volume hierarchy, so a ray does not have to be tested against every shape
+
+extern crate cgmath;
+
+use super::aabb::Aabb;
+use super::rays;
+use super::shapes::Hit;
+use cgmath::*;
+
+/// The number of buckets the centroids are sorted into when looking for the best split
+const BUCKETS: usize = 12;
+
+/// Leaves with this many primitives or less are never split
+const MAX_LEAF_SIZE: usize = 2;
+
+/// The cost of traversing a node relative to intersecting a primitive
+const TRAVERSAL_COST: f32 = 0.5;
+
+/// Nodes this deep are always leaves, which bounds the size of the traversal stack
+const MAX_DEPTH: usize = 60;
+
+enum Node {
+    /// A range of primitives in `Bvh::indices`
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        count: usize,
+    },
+    /// The first child comes directly after this node, the second is at `second`
+    Interior {
+        bounds: Aabb,
+        second: usize,
+        axis: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } => bounds,
+            Node::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A tree of bounding boxes over a list of primitives, built with the surface area heuristic.
+/// The primitives are refered to by their index in the list the tree was built from.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+/// A primitive while building the tree
+struct Primitive {
+    index: usize,
+    bounds: Aabb,
+    centroid: Point3<f32>,
+}
+
+impl Bvh {
+    /// Builds a tree over the bounding boxes of some primitives
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut primitives: Vec<Primitive> = bounds
+            .iter()
+            .enumerate()
+            .map(|(index, bounds)| Primitive {
+                index,
+                bounds: *bounds,
+                centroid: bounds.centroid(),
+            })
+            .collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * primitives.len()),
+            indices: Vec::with_capacity(primitives.len()),
+        };
+
+        if !primitives.is_empty() {
+            bvh.build_node(&mut primitives, 0);
+        }
+
+        bvh
+    }
+
+    /// Adds a node for the primitives, and the nodes below it
+    fn build_node(&mut self, primitives: &mut [Primitive], depth: usize) {
+        let bounds = primitives.iter().fold(Aabb::empty(), |aabb, primitive| {
+            aabb.union(&primitive.bounds)
+        });
+
+        let split = if primitives.len() > MAX_LEAF_SIZE && depth < MAX_DEPTH {
+            Bvh::find_split(primitives, &bounds)
+        } else {
+            None
+        };
+
+        match split {
+            Some((axis, mid)) => {
+                let node = self.nodes.len();
+                self.nodes.push(Node::Interior {
+                    bounds,
+                    second: 0,
+                    axis,
+                });
+
+                let (first, second) = primitives.split_at_mut(mid);
+                self.build_node(first, depth + 1);
+                let second_node = self.nodes.len();
+                self.build_node(second, depth + 1);
+
+                if let Node::Interior { second, .. } = &mut self.nodes[node] {
+                    *second = second_node;
+                }
+            }
+            None => {
+                self.nodes.push(Node::Leaf {
+                    bounds,
+                    start: self.indices.len(),
+                    count: primitives.len(),
+                });
+                self.indices
+                    .extend(primitives.iter().map(|primitive| primitive.index));
+            }
+        }
+    }
+
+    /// Finds the cheapest split according to the surface area heuristic, and partitions the
+    /// primitives around it. Returns the axis and the number of primitives on the first side,
+    /// or `None` if a leaf is cheaper.
+    fn find_split(primitives: &mut [Primitive], bounds: &Aabb) -> Option<(usize, usize)> {
+        let centroids = primitives.iter().fold(Aabb::empty(), |aabb, primitive| {
+            aabb.grow(primitive.centroid)
+        });
+
+        let size = centroids.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+
+        let extent = size[axis];
+        if extent <= 0. {
+            // Every centroid is in the same place, so there is nothing to split
+            return None;
+        }
+
+        let bucket = |primitive: &Primitive| -> usize {
+            let offset = (primitive.centroid[axis] - centroids.min[axis]) / extent;
+            ((offset * BUCKETS as f32) as usize).min(BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; BUCKETS];
+        let mut boxes = [Aabb::empty(); BUCKETS];
+        for primitive in primitives.iter() {
+            let b = bucket(primitive);
+            counts[b] += 1;
+            boxes[b] = boxes[b].union(&primitive.bounds);
+        }
+
+        // The cost of splitting after each bucket
+        let area = bounds.surface_area();
+        let mut best: Option<(usize, f32)> = None;
+        for split in 0..BUCKETS - 1 {
+            let (mut first, mut first_count) = (Aabb::empty(), 0);
+            for b in 0..=split {
+                first = first.union(&boxes[b]);
+                first_count += counts[b];
+            }
+            let (mut second, mut second_count) = (Aabb::empty(), 0);
+            for b in split + 1..BUCKETS {
+                second = second.union(&boxes[b]);
+                second_count += counts[b];
+            }
+
+            if first_count == 0 || second_count == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + (first_count as f32 * first.surface_area()
+                    + second_count as f32 * second.surface_area())
+                    / area;
+
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((split, cost));
+            }
+        }
+
+        let (split, cost) = best?;
+        if cost >= primitives.len() as f32 && primitives.len() <= 4 * MAX_LEAF_SIZE {
+            return None;
+        }
+
+        // Partition the primitives so the ones in the first buckets come first
+        let mut mid = 0;
+        for i in 0..primitives.len() {
+            if bucket(&primitives[i]) <= split {
+                primitives.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        Some((axis, mid))
+    }
+
+    /// Finds the closest hit along the ray. `intersection` is called with the index of every
+    /// primitive whose bounding box is hit by the ray before the closest hit found so far.
+    pub fn closest<F>(&self, ray: &rays::Ray, mut intersection: F) -> Option<(usize, Hit)>
+    where
+        F: FnMut(usize) -> Option<Hit>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_direction = Vector3::new(
+            1. / ray.direction.x,
+            1. / ray.direction.y,
+            1. / ray.direction.z,
+        );
+
+        let mut closest: Option<(usize, Hit)> = None;
+        // Every node on the stack is a child of a different node on the path from the root
+        let mut stack = [0; MAX_DEPTH + 1];
+        let mut size = 1;
+
+        while size > 0 {
+            size -= 1;
+            let index = stack[size];
+            let t_max = closest.as_ref().map_or(f32::INFINITY, |(_, hit)| hit.t);
+
+            let node = &self.nodes[index];
+            if !node.bounds().hit(ray, inv_direction, t_max) {
+                continue;
+            }
+
+            match node {
+                Node::Leaf { start, count, .. } => {
+                    for &primitive in &self.indices[*start..*start + *count] {
+                        if let Some(hit) = intersection(primitive) {
+                            if closest
+                                .as_ref()
+                                .is_none_or(|(_, closest)| hit.t < closest.t)
+                            {
+                                closest = Some((primitive, hit));
+                            }
+                        }
+                    }
+                }
+                Node::Interior { second, axis, .. } => {
+                    let first = index + 1;
+                    // Visit the child closest to the ray origin first
+                    let (near, far) = if ray.direction[*axis] < 0. {
+                        (*second, first)
+                    } else {
+                        (first, *second)
+                    };
+                    stack[size] = far;
+                    stack[size + 1] = near;
+                    size += 2;
+                }
+            }
+        }
+
+        closest
+    }
+}
@@ -0,0 +1,122 @@
+//! The command line arguments
+
+use super::image::Format;
+use super::sampling::{Filter, Pattern, Sampler};
+use super::scene::Integrator;
+use super::tonemap::ToneMap;
+
+pub const USAGE: &str = "\
+Usage:
+    ray-tracer [SCENE] [MODEL.obj]
+        Opens a window and renders the scene interactively
+    ray-tracer --output FILE [OPTIONS] [SCENE] [MODEL.obj]
+        Renders the scene without a window and saves the image
+
+A built in scene is used if no scene file is given, and an OBJ model is added to the scene.
+
+Options:
+    -o, --output FILE      Where to save the image, as .ppm, .png or .hdr
+    -f, --format NAME      p3, p6, png, png16 or hdr, if not guessed from the file name
+    -w, --width PIXELS     The width of the image, 640 if not given
+    -h, --height PIXELS    The height of the image, 480 if not given
+    -s, --samples N        Samples for each pixel, 16 if not given
+    --pattern NAME         grid, stratified, halton or sobol
+    --filter NAME          box, tent, gaussian or mitchell
+    --path-tracing         Use the path tracer, even if the scene uses the Whitted ray tracer
+    --exposure STOPS       Makes the image brighter or darker, 0 if not given
+    --tonemap NAME         clamp, reinhard or aces
+    --linear               Save linear colors instead of encoding them as sRGB
+    --help                 Show this message";
+
+/// What to render and how
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// The scene file to render
+    pub scene: Option<String>,
+    /// An OBJ model to add to the scene
+    pub model: Option<String>,
+    /// Where to save the image. If there is none, a window is opened instead.
+    pub output: Option<String>,
+    /// The format of the saved image, if it should not be guessed from the file name
+    pub format: Option<Format>,
+    pub width: usize,
+    pub height: usize,
+    pub sampler: Sampler,
+    /// The integrator to use instead of the one of the scene
+    pub integrator: Option<Integrator>,
+    pub tone_map: ToneMap,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene: None,
+            model: None,
+            output: None,
+            format: None,
+            width: 640,
+            height: 480,
+            sampler: Sampler::new(Pattern::Sobol, Filter::Gaussian, 16),
+            integrator: None,
+            tone_map: ToneMap::default(),
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Reads the options from the arguments, without the name of the program
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "-o" | "--output" => options.output = Some(value()?),
+                "-f" | "--format" => options.format = Some(value()?.parse()?),
+                "-w" | "--width" => options.width = number(&arg, &value()?)?,
+                "-h" | "--height" => options.height = number(&arg, &value()?)?,
+                "-s" | "--samples" => options.sampler.samples = number(&arg, &value()?)?,
+                "--pattern" => options.sampler.pattern = value()?.parse()?,
+                "--filter" => options.sampler.set_filter(value()?.parse()?),
+                "--path-tracing" => options.integrator = Some(Integrator::PathTracing),
+                "--exposure" => {
+                    let stops = value()?;
+                    options.tone_map.exposure = stops
+                        .parse()
+                        .map_err(|_| format!("{} needs a number, not {}", arg, stops))?
+                }
+                "--tonemap" => options.tone_map.operator = value()?.parse()?,
+                "--linear" => options.tone_map.srgb = false,
+                "--help" => options.help = true,
+                _ if arg.starts_with('-') => {
+                    return Err(format!("Unknown option {}\n\n{}", arg, USAGE))
+                }
+                _ if arg.ends_with(".obj") && options.model.is_none() => options.model = Some(arg),
+                _ if !arg.ends_with(".obj") && options.scene.is_none() => options.scene = Some(arg),
+                _ => return Err(format!("Unexpected argument {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// A whole number above zero
+fn number<T: std::str::FromStr + Default + PartialOrd>(
+    option: &str,
+    value: &str,
+) -> Result<T, String> {
+    match value.parse() {
+        Ok(n) if n > T::default() => Ok(n),
+        _ => Err(format!(
+            "{} needs a number above zero, not {}",
+            option, value
+        )),
+    }
+}
@@ -0,0 +1,160 @@
+//! Constructive solid geometry, where shapes are made by combining the insides of other shapes.
+//!
+//! Only shapes that have an inside, reported by `Shape::intervals`, can be combined. A shape
+//! without one, like a triangle, counts as empty.
+
+use super::aabb::Aabb;
+use super::rays;
+use super::shapes::{Hit, Shape, Span, MIN_T};
+
+use std::cmp::Ordering;
+
+/// Everything inside either of the shapes
+pub struct Union<A: Shape, B: Shape> {
+    a: A,
+    b: B,
+}
+
+impl<A: Shape, B: Shape> Union<A, B> {
+    pub fn new(a: A, b: B) -> Union<A, B> {
+        Union { a, b }
+    }
+}
+
+impl<A: Shape, B: Shape> Shape for Union<A, B> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        first_hit(&self.intervals(ray)?)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.a.bounds()?.union(&self.b.bounds()?))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        Some(combine(spans(&self.a, ray), spans(&self.b, ray), |a, b| {
+            a || b
+        }))
+    }
+}
+
+/// Everything inside both of the shapes
+pub struct Intersection<A: Shape, B: Shape> {
+    a: A,
+    b: B,
+}
+
+impl<A: Shape, B: Shape> Intersection<A, B> {
+    pub fn new(a: A, b: B) -> Intersection<A, B> {
+        Intersection { a, b }
+    }
+}
+
+impl<A: Shape, B: Shape> Shape for Intersection<A, B> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        first_hit(&self.intervals(ray)?)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match (self.a.bounds(), self.b.bounds()) {
+            (Some(a), Some(b)) => Some(a.intersection(&b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        Some(combine(spans(&self.a, ray), spans(&self.b, ray), |a, b| {
+            a && b
+        }))
+    }
+}
+
+/// Everything inside the first shape but not inside the second.
+/// The surface left by the second shape faces into it, so its normals are flipped.
+pub struct Difference<A: Shape, B: Shape> {
+    a: A,
+    b: B,
+}
+
+impl<A: Shape, B: Shape> Difference<A, B> {
+    pub fn new(a: A, b: B) -> Difference<A, B> {
+        Difference { a, b }
+    }
+}
+
+impl<A: Shape, B: Shape> Shape for Difference<A, B> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        first_hit(&self.intervals(ray)?)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // Taking something away never makes the shape larger
+        self.a.bounds()
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let flip = |hit: Hit| Hit {
+            normal: hit.normal.map(|normal| -normal),
+            ..hit
+        };
+        let b = spans(&self.b, ray)
+            .into_iter()
+            .map(|span| Span {
+                enter: flip(span.enter),
+                exit: flip(span.exit),
+            })
+            .collect();
+
+        Some(combine(spans(&self.a, ray), b, |a, b| a && !b))
+    }
+}
+
+/// The inside of a shape along a ray, where a shape without an inside is empty
+fn spans<S: Shape>(shape: &S, ray: &rays::Ray) -> Vec<Span> {
+    shape.intervals(ray).unwrap_or_default()
+}
+
+/// Combines the insides of two shapes along a ray. `inside` tells if a point is inside the
+/// combined shape from whether it is inside each of the shapes.
+fn combine<F: Fn(bool, bool) -> bool>(a: Vec<Span>, b: Vec<Span>, inside: F) -> Vec<Span> {
+    // Every place where the ray enters or leaves one of the shapes, as (hit, from the first
+    // shape, entering)
+    let mut events: Vec<(Hit, bool, bool)> = Vec::with_capacity(2 * (a.len() + b.len()));
+    for (spans, first) in [(a, true), (b, false)] {
+        for span in spans {
+            events.push((span.enter, first, true));
+            events.push((span.exit, first, false));
+        }
+    }
+    events.sort_by(|(a, ..), (b, ..)| a.t.partial_cmp(&b.t).unwrap_or(Ordering::Equal));
+
+    // Counts rather than flags, so shapes with overlapping spans are handled too
+    let (mut in_a, mut in_b) = (0, 0);
+    let mut enter = None;
+    let mut result = Vec::new();
+    for (hit, first, entering) in events {
+        let count = if first { &mut in_a } else { &mut in_b };
+        *count += if entering { 1 } else { -1 };
+
+        match (enter, inside(in_a > 0, in_b > 0)) {
+            (None, true) => enter = Some(hit),
+            (Some(start), false) => {
+                result.push(Span {
+                    enter: start,
+                    exit: hit,
+                });
+                enter = None;
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// The first place in front of the ray where it crosses the surface of the combined shape
+fn first_hit(spans: &[Span]) -> Option<Hit> {
+    spans
+        .iter()
+        .flat_map(|span| [span.enter, span.exit])
+        .find(|hit| hit.t > MIN_T)
+        .filter(|hit| hit.t.is_finite())
+}
@@ -0,0 +1,295 @@
+//! Saving rendered images to files
+
+use super::render::Rgb;
+use super::tonemap::ToneMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// The file formats images can be saved in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// PPM with the numbers written as text (P3)
+    PpmText,
+    /// PPM with the numbers written as bytes (P6)
+    Ppm,
+    /// PNG with 8 bits per channel
+    Png,
+    /// PNG with 16 bits per channel
+    Png16,
+    /// Radiance RGBE, which keeps the linear colors, even those brighter than 1
+    Hdr,
+}
+
+impl Format {
+    /// Guesses the format from the extension of a path
+    pub fn from_path(path: &str) -> Result<Format, String> {
+        let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "ppm" => Ok(Format::Ppm),
+            "png" => Ok(Format::Png),
+            "hdr" => Ok(Format::Hdr),
+            _ => Err(format!(
+                "{}: unknown image format, use .ppm, .png or .hdr",
+                path
+            )),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "p3" => Ok(Format::PpmText),
+            "p6" | "ppm" => Ok(Format::Ppm),
+            "png" | "png8" => Ok(Format::Png),
+            "png16" => Ok(Format::Png16),
+            "hdr" => Ok(Format::Hdr),
+            _ => Err(format!("Unknown image format {}", name)),
+        }
+    }
+}
+
+/// Saves an image to a file. The pixels are linear colors, row by row from the top.
+/// They are tone mapped, except for the HDR format which keeps them as they are.
+pub fn save(
+    path: &str,
+    format: Format,
+    w: usize,
+    h: usize,
+    pixels: &[Rgb],
+    tone_map: &ToneMap,
+) -> Result<(), String> {
+    let error = |e: io::Error| format!("{}: {}", path, e);
+    let mut file = BufWriter::new(File::create(path).map_err(error)?);
+    write(&mut file, format, w, h, pixels, tone_map).map_err(error)?;
+    file.flush().map_err(error)
+}
+
+/// Writes an image in a format. The pixels are linear colors, row by row from the top.
+/// They are tone mapped, except for the HDR format which keeps them as they are.
+pub fn write<W: Write>(
+    writer: &mut W,
+    format: Format,
+    w: usize,
+    h: usize,
+    pixels: &[Rgb],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    assert_eq!(
+        pixels.len(),
+        w * h,
+        "the image has the wrong number of pixels"
+    );
+    let mapped = || -> Vec<Rgb> { pixels.iter().map(|&c| tone_map.map(c)).collect() };
+    match format {
+        Format::PpmText => write_ppm_text(writer, w, h, &mapped()),
+        Format::Ppm => write_ppm(writer, w, h, &mapped()),
+        Format::Png => write_png(writer, w, h, &mapped(), false),
+        Format::Png16 => write_png(writer, w, h, &mapped(), true),
+        Format::Hdr => write_hdr(writer, w, h, pixels),
+    }
+}
+
+/// A channel between 0 and 1 as a whole number between 0 and max
+fn quantize(c: f32, max: u32) -> u32 {
+    (c.clamp(0., 1.) * max as f32 + 0.5) as u32
+}
+
+/// Lines of a text PPM should be at most 70 characters long, and five pixels of up to
+/// `255 255 255` with two spaces between them is 63
+const PPM_PIXELS_PER_LINE: usize = 5;
+
+fn write_ppm_text<W: Write>(writer: &mut W, w: usize, h: usize, pixels: &[Rgb]) -> io::Result<()> {
+    writeln!(writer, "P3\n{} {}\n255", w, h)?;
+    // Every row starts on a new line, so the image is still easy to read
+    let lines = pixels
+        .chunks(w.max(1))
+        .flat_map(|row| row.chunks(PPM_PIXELS_PER_LINE));
+    for pixels in lines {
+        let line: Vec<String> = pixels
+            .iter()
+            .map(|c| {
+                format!(
+                    "{} {} {}",
+                    quantize(c.x, 255),
+                    quantize(c.y, 255),
+                    quantize(c.z, 255)
+                )
+            })
+            .collect();
+        writeln!(writer, "{}", line.join("  "))?;
+    }
+    Ok(())
+}
+
+fn write_ppm<W: Write>(writer: &mut W, w: usize, h: usize, pixels: &[Rgb]) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", w, h)?;
+    let bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|c| [c.x, c.y, c.z])
+        .map(|c| quantize(c, 255) as u8)
+        .collect();
+    writer.write_all(&bytes)
+}
+
+/// The CRC-32 used by PNG chunks
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// The Adler-32 checksum at the end of zlib data
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Wraps data in zlib without compressing it, using stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+    writer.write_all(&checked)?;
+    writer.write_all(&crc32(&checked).to_be_bytes())
+}
+
+fn write_png<W: Write>(
+    writer: &mut W,
+    w: usize,
+    h: usize,
+    pixels: &[Rgb],
+    sixteen_bits: bool,
+) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(w as u32).to_be_bytes());
+    header.extend_from_slice(&(h as u32).to_be_bytes());
+    // Bit depth, truecolor, deflate, no filtering and no interlacing
+    header.extend_from_slice(&[if sixteen_bits { 16 } else { 8 }, 2, 0, 0, 0]);
+    write_png_chunk(writer, b"IHDR", &header)?;
+
+    // Every row starts with the filter type, which is always none here
+    let mut data = Vec::new();
+    for row in pixels.chunks(w.max(1)) {
+        data.push(0);
+        for c in row {
+            for &channel in &[c.x, c.y, c.z] {
+                if sixteen_bits {
+                    data.extend_from_slice(&(quantize(channel, 65535) as u16).to_be_bytes());
+                } else {
+                    data.push(quantize(channel, 255) as u8);
+                }
+            }
+        }
+    }
+    write_png_chunk(writer, b"IDAT", &zlib_stored(&data))?;
+    write_png_chunk(writer, b"IEND", &[])
+}
+
+/// A color as three mantissas sharing an exponent.
+/// Channels too bright for the format, including infinite ones, become the brightest it can
+/// hold, and NaN becomes black.
+fn rgbe(c: Rgb) -> [u8; 4] {
+    let largest = 255. / 256. * 2f32.powi(127);
+    let c = c.map(|c| if c.is_nan() { 0. } else { c.clamp(0., largest) });
+
+    let brightest = c.x.max(c.y).max(c.z);
+    if brightest < 1e-32 {
+        return [0; 4];
+    }
+    let exponent = (brightest.log2().floor() as i32 + 1).clamp(-128, 127);
+    let scale = 256. / 2f32.powi(exponent);
+    let mantissa = |c: f32| (c * scale).min(255.) as u8;
+    [
+        mantissa(c.x),
+        mantissa(c.y),
+        mantissa(c.z),
+        (exponent + 128) as u8,
+    ]
+}
+
+fn write_hdr<W: Write>(writer: &mut W, w: usize, h: usize, pixels: &[Rgb]) -> io::Result<()> {
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        h, w
+    )?;
+    let bytes: Vec<u8> = pixels.iter().flat_map(|&c| rgbe(c).to_vec()).collect();
+    writer.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    /// The color an RGBE pixel stands for
+    fn decode([r, g, b, e]: [u8; 4]) -> Rgb {
+        let scale = 2f32.powi(e as i32 - 128) / 256.;
+        Vector3::new(r as f32, g as f32, b as f32) * scale
+    }
+
+    #[test]
+    fn rgbe_keeps_ordinary_colors() {
+        let color = Vector3::new(0.5, 1., 3.);
+        let decoded = decode(rgbe(color));
+        for axis in 0..3 {
+            assert!((decoded[axis] - color[axis]).abs() <= color[axis] / 64.);
+        }
+    }
+
+    #[test]
+    fn rgbe_limits_huge_and_infinite_colors() {
+        for &huge in &[1e30, 2f32.powi(127), f32::MAX, f32::INFINITY] {
+            let [r, g, b, e] = rgbe(Vector3::new(huge, 1., 0.));
+            assert!(r >= 128, "{} became {:?}", huge, [r, g, b, e]);
+            assert!(e > 128);
+        }
+        assert_eq!(rgbe(Vector3::new(f32::INFINITY, 0., 0.)), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rgbe_makes_nan_black() {
+        assert_eq!(rgbe(Vector3::new(f32::NAN, f32::NAN, f32::NAN)), [0; 4]);
+        assert_eq!(rgbe(Vector3::new(f32::NAN, 1., 0.))[0], 0);
+    }
+}
@@ -8,15 +8,21 @@ use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
 use super::rays;
+use super::render::Settings;
+use super::scene::Integrator;
 
-/// Check for events and update variables depending on the events
+/// Check for events and update variables depending on the events.
+/// Returns true if the camera moved or anything else changed what the image looks like.
 pub fn check_keyboard(
     event_pump: &mut sdl2::EventPump,
     running: &mut bool,
     origin: &mut Point3<f32>,
     camdir: &mut rays::CamDir,
     scale: &mut usize,
-) {
+    integrator: &mut Integrator,
+    settings: &mut Settings,
+) -> bool {
+    let mut changed = false;
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. }
@@ -32,6 +38,7 @@ pub fn check_keyboard(
             } => {
                 origin.z += 1.;
                 camdir.update(*origin);
+                changed = true;
             }
             Event::KeyDown {
                 keycode: Some(Keycode::S),
@@ -39,6 +46,7 @@ pub fn check_keyboard(
             } => {
                 origin.z -= 1.;
                 camdir.update(*origin);
+                changed = true;
             }
             Event::KeyDown {
                 keycode: Some(Keycode::D),
@@ -46,6 +54,7 @@ pub fn check_keyboard(
             } => {
                 origin.x += 1.;
                 camdir.update(*origin);
+                changed = true;
             }
             Event::KeyDown {
                 keycode: Some(Keycode::A),
@@ -53,6 +62,7 @@ pub fn check_keyboard(
             } => {
                 origin.x -= 1.;
                 camdir.update(*origin);
+                changed = true;
             }
             Event::KeyDown {
                 keycode: Some(Keycode::Space),
@@ -60,6 +70,7 @@ pub fn check_keyboard(
             } => {
                 origin.y += 1.;
                 camdir.update(*origin);
+                changed = true;
             }
             Event::KeyDown {
                 keycode: Some(Keycode::LShift),
@@ -67,16 +78,81 @@ pub fn check_keyboard(
             } => {
                 origin.y -= 1.;
                 camdir.update(*origin);
+                changed = true;
             }
             Event::KeyDown {
                 keycode: Some(Keycode::P),
                 ..
-            } => *scale = 8,
+            } => {
+                *scale = 8;
+                changed = true;
+            }
             Event::KeyDown {
                 keycode: Some(Keycode::M),
                 ..
-            } => *scale = 1,
+            } => {
+                *scale = 1;
+                changed = true;
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::I),
+                ..
+            } => {
+                *integrator = integrator.toggle();
+                changed = true;
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::J),
+                ..
+            } => {
+                settings.sampler.pattern = settings.sampler.pattern.next();
+                changed = true;
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::F),
+                ..
+            } => {
+                settings
+                    .sampler
+                    .set_filter(settings.sampler.filter().next());
+                changed = true;
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::N),
+                ..
+            } => {
+                // 1, 2, 4 and up to 64 samples for each pixel in every frame
+                settings.sampler.samples = if settings.sampler.samples >= 64 {
+                    1
+                } else {
+                    settings.sampler.samples * 2
+                };
+                changed = true;
+            }
+            // The tone mapping is done after the samples are added up, so the image does not
+            // have to start over when it changes
+            Event::KeyDown {
+                keycode: Some(Keycode::Equals),
+                ..
+            }
+            | Event::KeyDown {
+                keycode: Some(Keycode::KpPlus),
+                ..
+            } => settings.tone_map.exposure += 0.5,
+            Event::KeyDown {
+                keycode: Some(Keycode::Minus),
+                ..
+            }
+            | Event::KeyDown {
+                keycode: Some(Keycode::KpMinus),
+                ..
+            } => settings.tone_map.exposure -= 0.5,
+            Event::KeyDown {
+                keycode: Some(Keycode::T),
+                ..
+            } => settings.tone_map.operator = settings.tone_map.operator.next(),
             _ => {}
         }
     }
+    changed
 }
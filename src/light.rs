@@ -0,0 +1,152 @@
+//! Light sources that illuminate the shapes in a scene
+
+extern crate cgmath;
+
+use super::render::Rgb;
+use cgmath::*;
+
+/// A source of light. The light from point and spot lights falls off with the square of the
+/// distance, while directional lights are infinitely far away and equally strong everywhere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    /// Shines equally in every direction from a point
+    Point {
+        position: Point3<f32>,
+        color: Rgb,
+        intensity: f32,
+    },
+    /// Shines in one direction everywhere, like the sun
+    Directional {
+        /// The direction the light travels in
+        direction: Vector3<f32>,
+        color: Rgb,
+        intensity: f32,
+    },
+    /// Shines in a cone from a point
+    Spot {
+        position: Point3<f32>,
+        /// The direction the cone points in
+        direction: Vector3<f32>,
+        /// The angle between the direction and the edge of the cone
+        angle: Rad<f32>,
+        /// How much of the cone that fades out towards the edge, between 0 and 1
+        softness: f32,
+        color: Rgb,
+        intensity: f32,
+    },
+}
+
+impl Light {
+    /// A white light shining from a point
+    pub fn point(position: Point3<f32>, intensity: f32) -> Light {
+        Light::Point {
+            position,
+            color: Vector3::new(1., 1., 1.),
+            intensity,
+        }
+    }
+
+    /// A white light shining in one direction
+    pub fn directional(direction: Vector3<f32>, intensity: f32) -> Light {
+        Light::Directional {
+            direction: direction.normalize(),
+            color: Vector3::new(1., 1., 1.),
+            intensity,
+        }
+    }
+
+    /// A white light shining in a cone from a point, fading out over the outer fifth of the cone
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        angle: Rad<f32>,
+        intensity: f32,
+    ) -> Light {
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            angle,
+            softness: 0.2,
+            color: Vector3::new(1., 1., 1.),
+            intensity,
+        }
+    }
+
+    /// The same light with another color
+    pub fn with_color(self, new_color: Rgb) -> Light {
+        let mut light = self;
+        match &mut light {
+            Light::Point { color, .. }
+            | Light::Directional { color, .. }
+            | Light::Spot { color, .. } => *color = new_color,
+        }
+        light
+    }
+
+    /// Where the light is, if it is not infinitely far away
+    pub fn position(&self) -> Option<Point3<f32>> {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => Some(*position),
+            Light::Directional { .. } => None,
+        }
+    }
+
+    /// The color of the light times its intensity
+    pub fn power(&self) -> Rgb {
+        match self {
+            Light::Point {
+                color, intensity, ..
+            }
+            | Light::Directional {
+                color, intensity, ..
+            }
+            | Light::Spot {
+                color, intensity, ..
+            } => color * *intensity,
+        }
+    }
+
+    /// The direction from a point towards the light, and how far away the light is
+    pub fn towards(&self, point: Point3<f32>) -> (Vector3<f32>, f32) {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => {
+                let offset = position - point;
+                let distance = offset.magnitude();
+                (offset / distance, distance)
+            }
+            Light::Directional { direction, .. } => (-*direction, f32::INFINITY),
+        }
+    }
+
+    /// The light arriving at a point, if nothing is in the way
+    pub fn radiance(&self, point: Point3<f32>) -> Rgb {
+        match self {
+            Light::Point { position, .. } => self.power() / (position - point).magnitude2(),
+            Light::Directional { .. } => self.power(),
+            Light::Spot {
+                position,
+                direction,
+                angle,
+                softness,
+                ..
+            } => {
+                let offset = point - position;
+                let distance2 = offset.magnitude2();
+                let cos = direction.dot(offset) / distance2.sqrt();
+
+                let outer = angle.cos();
+                let inner = (*angle * (1. - softness.clamp(0., 1.))).cos();
+                let cone = if cos >= inner {
+                    1.
+                } else if cos <= outer {
+                    0.
+                } else {
+                    let x = (cos - outer) / (inner - outer);
+                    x * x * (3. - 2. * x)
+                };
+
+                self.power() * (cone / distance2)
+            }
+        }
+    }
+}
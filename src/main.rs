@@ -6,15 +6,29 @@ extern crate rayon;
 extern crate sdl2;
 
 use cgmath::Point3;
-use cgmath::Vector3;
-use rayon::prelude::*;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
+use std::time::Instant;
 
+pub mod aabb;
+pub mod bvh;
+pub mod cli;
+pub mod csg;
+pub mod image;
 pub mod keyboard;
+pub mod light;
+pub mod material;
+pub mod mesh;
+pub mod obj;
 pub mod rays;
 pub mod render;
+pub mod sampling;
+pub mod scene;
+pub mod scenefile;
+pub mod sdf;
 pub mod shapes;
+pub mod tonemap;
+pub mod transform;
 
 fn color_to_rgb(color: u32) -> Color {
     let b = color % 256;
@@ -24,7 +38,96 @@ fn color_to_rgb(color: u32) -> Color {
     Color::RGB(r as u8, g as u8, b as u8)
 }
 
+/// The title of the window
+const TITLE: &str = "SDL2";
+
+/// The scene that is rendered when no scene file is given
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.scene");
+
 fn main() -> Result<(), String> {
+    let options = cli::Options::parse(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    let file = load_scene(&options)?;
+
+    match &options.output {
+        Some(path) => {
+            let camera = camera(&file);
+            let camdir = rays::CamDir::new(camera.position, camera.target);
+            let mut scene = file.into_scene();
+            if let Some(integrator) = options.integrator {
+                scene.integrator = integrator;
+            }
+            render_to_file(&scene, &camdir, &options, path)
+        }
+        None => run_window(file, &options),
+    }
+}
+
+/// Loads the scene file given on the command line, or the default scene, and adds the model
+fn load_scene(options: &cli::Options) -> Result<scenefile::SceneFile, String> {
+    let mut file = match &options.scene {
+        Some(path) => scenefile::load(path),
+        None => scenefile::parse("default.scene", DEFAULT_SCENE, |path| {
+            obj::load(path).map_err(|e| e.to_string())
+        }),
+    }
+    .map_err(|e| e.to_string())?;
+    if let Some(path) = &options.model {
+        file.add_model(obj::load(path).map_err(|e| e.to_string())?);
+    }
+    Ok(file)
+}
+
+/// The camera of the scene file, or a camera looking at the origin from a bit away
+fn camera(file: &scenefile::SceneFile) -> scenefile::Camera {
+    file.camera.unwrap_or(scenefile::Camera {
+        position: Point3::new(0., 2., -10.),
+        target: Point3::new(0., 0., 0.),
+    })
+}
+
+/// Renders the scene without opening a window, and saves the image
+fn render_to_file(
+    scene: &scene::Scene,
+    camdir: &rays::CamDir,
+    options: &cli::Options,
+    path: &str,
+) -> Result<(), String> {
+    let format = match options.format {
+        Some(format) => format,
+        None => image::Format::from_path(path)?,
+    };
+
+    let start = Instant::now();
+    let mut film = render::Film::new(options.width, options.height);
+    render::render_pass(scene, camdir, &options.sampler, &mut film, 0);
+    image::save(
+        path,
+        format,
+        film.width(),
+        film.height(),
+        &film.image(),
+        &options.tone_map,
+    )?;
+
+    println!(
+        "Rendered {}x{} with {} samples per pixel in {:.2} s to {}",
+        options.width,
+        options.height,
+        options.sampler.samples,
+        start.elapsed().as_secs_f32(),
+        path
+    );
+    Ok(())
+}
+
+/// Opens a window and renders the scene over and over, moving the camera with the keyboard.
+/// The scene file is loaded again whenever it is saved.
+fn run_window(file: scenefile::SceneFile, options: &cli::Options) -> Result<(), String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
@@ -35,7 +138,7 @@ fn main() -> Result<(), String> {
     //let h: usize = 1280;
 
     let window = video_subsystem
-        .window("SDL2", w as u32, h as u32)
+        .window(TITLE, w as u32, h as u32)
         .position_centered()
         .build()
         .map_err(|e| e.to_string())?;
@@ -51,80 +154,111 @@ fn main() -> Result<(), String> {
     let mut scale = 8;
     //let mut scale = 20;
 
-    let mut origin: Point3<f32> = Point3::new(0., 2., -10.);
+    let camera = camera(&file);
+    let mut origin = camera.position;
+    let mut camdir = rays::CamDir::new(origin, camera.target);
 
-    let mut camdir = rays::CamDir::new(origin, Point3::new(0., 0., 0.));
+    let mut watcher = options.scene.as_ref().map(scenefile::Watcher::new);
 
-    let mut pixels = vec![0; w * h];
-    let mut bands: Vec<(usize, &mut [u32])> =
-        pixels.chunks_mut(w as usize / scale).enumerate().collect();
+    // Starts out the way the command line asked, and can then be changed with the keyboard
+    let mut settings = render::Settings {
+        sampler: options.sampler,
+        tone_map: options.tone_map,
+    };
 
-    let hyperboloid =
-        shapes::Hyperboloid::new(-10., Point3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
-    let spheroid = shapes::Spheroid::new(1., Point3::new(0., 0., 0.), Vector3::new(1., 2., 1.));
-    let plane = shapes::Plane::new(Vector3::new(0., 1., 0.), Point3::new(0., -5.1, 0.));
-    //let torus = shapes::Torus::new(0.3, 1., Point3::new(0., 0., 0.));
-    //let cylinder = shapes::Cylinder::new(0.9, Point3::new(0., 0., 0.));
+    // The samples of the pixels added up while the camera stands still
+    let mut film = render::Film::new(w / scale, h / scale);
 
-    let shapes = &plane + &spheroid + &hyperboloid;
+    // Counts the frames, so every frame gets new random numbers
+    let mut frame: u64 = 0;
 
-    //let mut prev_scale = scale;
+    // The camera and integrator of the file, to know if the file changes them when it is loaded
+    // again
+    let mut file_camera = file.camera;
+    let mut file_integrator = file.integrator;
+    let mut scene = file.into_scene();
+    if let Some(integrator) = options.integrator {
+        scene.integrator = integrator;
+    }
 
     let mut running = true;
     while running {
-        keyboard::check_keyboard(
+        let changed = keyboard::check_keyboard(
             &mut event_pump,
             &mut running,
             &mut origin,
             &mut camdir,
             &mut scale,
+            &mut scene.integrator,
+            &mut settings,
         );
+        frame += 1;
 
         let (w, h) = canvas.output_size()?;
+        let ws = w as usize / scale;
+        let hs = h as usize / scale;
 
-        macro_rules! color_line {
-            ( $y:expr, $width:expr, $color:expr ) => {
-                for x in 0..$width {
-                    canvas.set_draw_color(color_to_rgb($color(x)));
-                    canvas.fill_rect(Rect::new(
-                        (x * scale) as i32,
-                        ($y * scale) as i32,
-                        scale as u32,
-                        scale as u32,
-                    ))?;
-                }
-            };
+        if changed || film.width() != ws || film.height() != hs {
+            film.reset(ws, hs);
         }
 
-        if scale < 50 {
-            for y in 0..h as usize / scale {
-                color_line!(y, w as usize / scale, |x| rays::Ray::from_camdir(
-                    &camdir,
-                    rays::CamDir::uv(x, y, w as usize / scale, h as usize / scale),
-                )
-                .intersection(&shapes));
-            }
-        } else {
-            (&mut bands)
-                .into_par_iter()
-                .weight_max()
-                .for_each(|(i, band)| {
-                    let ws = w as usize / scale;
-                    for x in 0..ws {
-                        band[x] = rays::Ray::from_camdir(
-                            &camdir,
-                            rays::CamDir::uv(x, *i, ws, h as usize / scale),
-                        )
-                        .intersection(&shapes);
-                    }
-                });
+        // Every frame adds another pass of samples to each pixel
+        render::render_pass(&scene, &camdir, &settings.sampler, &mut film, frame);
 
-            for (y, band) in &bands {
-                color_line!(*y, w as usize / scale, |x| band[x]);
+        // The colors are only turned into bytes here, after all samples are added up
+        let display = film.to_display(&settings.tone_map);
+        for y in 0..hs {
+            for x in 0..ws {
+                canvas.set_draw_color(color_to_rgb(display[x + y * ws]));
+                canvas.fill_rect(Rect::new(
+                    (x * scale) as i32,
+                    (y * scale) as i32,
+                    scale as u32,
+                    scale as u32,
+                ))?;
             }
         }
 
         canvas.present();
+
+        if watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            // A broken file is shown in the title, and the old scene is kept until the file is
+            // fixed
+            let title = match load_scene(options) {
+                Ok(file) => {
+                    // The camera is only moved if the file moved it, so it stays where it has
+                    // been moved to with the keyboard otherwise
+                    match file.camera {
+                        Some(camera) if file.camera != file_camera => {
+                            origin = camera.position;
+                            camdir = rays::CamDir::new(origin, camera.target);
+                        }
+                        _ => {}
+                    }
+                    file_camera = file.camera;
+
+                    // The same goes for the integrator, which may have been chosen with the
+                    // keyboard or on the command line
+                    let integrator = scene.integrator;
+                    let keep_integrator = file.integrator == file_integrator;
+                    file_integrator = file.integrator;
+                    scene = file.into_scene();
+                    if keep_integrator {
+                        scene.integrator = integrator;
+                    }
+                    film.reset(ws, hs);
+                    TITLE.to_string()
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    format!("{} - {}", TITLE, e)
+                }
+            };
+            canvas
+                .window_mut()
+                .set_title(&title)
+                .map_err(|e| e.to_string())?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,128 @@
+//! How the surface of a shape looks
+
+extern crate cgmath;
+
+use super::obj::MtlMaterial;
+use super::render::Rgb;
+use cgmath::*;
+
+/// The description of a surface, used when shading the points where rays hit a shape.
+/// The colors are linear rgb, where 1 is full intensity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    /// The color of the diffuse light reflected by the surface
+    pub color: Rgb,
+    /// How much of the light is reflected diffusely
+    pub diffuse: f32,
+    /// How strong the specular highlights are
+    pub specular: f32,
+    /// The higher the shininess, the smaller and sharper the specular highlights are
+    pub shininess: f32,
+    /// Light given off by the surface itself, which is not affected by shadows
+    pub emission: Rgb,
+    /// How much of the color comes from mirror reflections, between 0 and 1
+    pub reflectivity: f32,
+    /// How much of the light goes through the surface, between 0 and 1.
+    /// The light going through is tinted by the color.
+    pub transparency: f32,
+    /// The index of refraction of the inside of the shape, 1 for air and 1.5 for glass
+    pub ior: f32,
+}
+
+impl Default for Material {
+    /// A matte white surface
+    fn default() -> Material {
+        Material::new(Vector3::new(1., 1., 1.))
+    }
+}
+
+impl Material {
+    /// A matte surface with a color
+    pub fn new(color: Rgb) -> Material {
+        Material {
+            color,
+            diffuse: 1.,
+            specular: 0.,
+            shininess: 1.,
+            emission: Vector3::new(0., 0., 0.),
+            reflectivity: 0.,
+            transparency: 0.,
+            ior: 1.,
+        }
+    }
+
+    /// A clear material like glass or water, where the light is either reflected or refracted
+    pub fn dielectric(color: Rgb, ior: f32) -> Material {
+        Material {
+            diffuse: 0.,
+            specular: 1.,
+            shininess: 128.,
+            transparency: 1.,
+            ior,
+            ..Material::new(color)
+        }
+    }
+
+    /// Clear glass
+    pub fn glass() -> Material {
+        Material::dielectric(Vector3::new(1., 1., 1.), 1.5)
+    }
+
+    /// A surface that mirrors its surroundings, blended with a color
+    pub fn mirror(color: Rgb, reflectivity: f32) -> Material {
+        Material {
+            specular: 1.,
+            shininess: 64.,
+            reflectivity,
+            ..Material::new(color)
+        }
+    }
+
+    /// A shiny surface with a color and white highlights
+    pub fn shiny(color: Rgb, specular: f32, shininess: f32) -> Material {
+        Material {
+            specular,
+            shininess,
+            ..Material::new(color)
+        }
+    }
+
+    /// A surface that only gives off light
+    pub fn emissive(emission: Rgb) -> Material {
+        Material {
+            color: Vector3::new(0., 0., 0.),
+            diffuse: 0.,
+            emission,
+            ..Material::default()
+        }
+    }
+}
+
+impl<'a> From<&'a MtlMaterial> for Material {
+    fn from(mtl: &'a MtlMaterial) -> Material {
+        let strength = |color: Vector3<f32>| color.x.max(color.y).max(color.z);
+
+        // Illumination models 3, 5, 6 and 7 have ray traced reflections
+        let reflectivity = match mtl.illumination {
+            3 | 5 | 6 | 7 => strength(mtl.specular),
+            _ => 0.,
+        };
+
+        // Illumination models 6 and 7 have ray traced refraction
+        let transparency = match mtl.illumination {
+            6 | 7 => 1.,
+            _ => 1. - mtl.dissolve,
+        };
+
+        Material {
+            color: mtl.diffuse,
+            diffuse: 1.,
+            specular: strength(mtl.specular),
+            shininess: mtl.shininess.max(1.),
+            emission: mtl.emission,
+            reflectivity,
+            transparency: transparency.clamp(0., 1.),
+            ior: mtl.optical_density,
+        }
+    }
+}
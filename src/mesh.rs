@@ -0,0 +1,260 @@
+//! Triangles and meshes made out of triangles
+
+extern crate cgmath;
+
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::rays;
+use super::shapes::{Hit, Shape, MIN_T};
+use cgmath::*;
+
+/// How close to parallel a ray and a triangle can be, relative to the size of the triangle and
+/// the length of the direction, before they are treated as parallel
+const EPSILON: f32 = 1e-7;
+
+/// Möller–Trumbore ray/triangle intersection.
+/// Returns the distance along the ray and the barycentric coordinates of the second and third
+/// vertex.
+fn moller_trumbore(
+    ray: &rays::Ray,
+    a: Point3<f32>,
+    b: Point3<f32>,
+    c: Point3<f32>,
+) -> Option<(f32, Vector2<f32>)> {
+    let e1 = b - a;
+    let e2 = c - a;
+
+    let p = ray.direction.cross(e2);
+    let det = e1.dot(p);
+    // The determinant grows with the size of the triangle, so a fixed threshold would miss tiny
+    // triangles and hit huge ones at grazing angles. Triangles without any area are skipped too.
+    if det.abs() <= EPSILON * e1.magnitude() * e2.magnitude() * ray.direction.magnitude() {
+        // The ray is parallel to the triangle
+        return None;
+    }
+    let inv_det = 1. / det;
+
+    let s = ray.origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(e1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = e2.dot(q) * inv_det;
+    if t <= MIN_T {
+        None
+    } else {
+        Some((t, Vector2::new(u, v)))
+    }
+}
+
+/// Interpolates the vertex normals of a triangle with barycentric coordinates
+fn interpolate(normals: [Vector3<f32>; 3], uv: Vector2<f32>) -> Vector3<f32> {
+    ((1. - uv.x - uv.y) * normals[0] + uv.x * normals[1] + uv.y * normals[2]).normalize()
+}
+
+/// A single triangle. The front of the triangle is the side where the vertices are in
+/// counter-clockwise order.
+pub struct Triangle {
+    vertices: [Point3<f32>; 3],
+    normals: Option<[Vector3<f32>; 3]>,
+}
+
+impl Triangle {
+    /// A flat triangle, where the normal is the same everywhere on the surface
+    pub fn new(a: Point3<f32>, b: Point3<f32>, c: Point3<f32>) -> Triangle {
+        Triangle {
+            vertices: [a, b, c],
+            normals: None,
+        }
+    }
+
+    /// A triangle where the normal is interpolated between the normals at the vertices
+    pub fn with_normals(vertices: [Point3<f32>; 3], normals: [Vector3<f32>; 3]) -> Triangle {
+        Triangle {
+            vertices,
+            normals: Some([
+                normals[0].normalize(),
+                normals[1].normalize(),
+                normals[2].normalize(),
+            ]),
+        }
+    }
+}
+
+impl Shape for Triangle {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let [a, b, c] = self.vertices;
+        let (t, uv) = moller_trumbore(ray, a, b, c)?;
+
+        let normal = match self.normals {
+            Some(normals) => interpolate(normals, uv),
+            None => (b - a).cross(c - a).normalize(),
+        };
+
+        Some(Hit {
+            t,
+            normal: Some(normal),
+            uv: Some(uv),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(self.vertices.iter().cloned()))
+    }
+}
+
+/// A mesh of triangles sharing a vertex buffer.
+/// Every triangle is three indices into the vertex buffer, and the triangles are sorted into a
+/// bounding volume hierarchy so large meshes are fast to intersect.
+pub struct TriangleMesh {
+    positions: Vec<Point3<f32>>,
+    /// Either empty, or one normal for each position
+    normals: Vec<Vector3<f32>>,
+    /// Either empty, or one texture coordinate for each position
+    texcoords: Vec<Vector2<f32>>,
+    indices: Vec<[u32; 3]>,
+    bvh: Bvh,
+    bounds: Aabb,
+}
+
+impl TriangleMesh {
+    /// Makes a mesh out of vertices and triangles.
+    /// The normals can be empty, in which case every triangle is flat, otherwise there must be
+    /// one normal for every vertex.
+    pub fn new(
+        positions: Vec<Point3<f32>>,
+        normals: Vec<Vector3<f32>>,
+        indices: Vec<[u32; 3]>,
+    ) -> Result<TriangleMesh, String> {
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(format!(
+                "The mesh has {} vertices but {} normals",
+                positions.len(),
+                normals.len()
+            ));
+        }
+
+        if let Some(index) = indices
+            .iter()
+            .flat_map(|triangle| triangle.iter())
+            .find(|&&index| index as usize >= positions.len())
+        {
+            return Err(format!(
+                "Index {} is out of bounds for a mesh with {} vertices",
+                index,
+                positions.len()
+            ));
+        }
+
+        let triangles: Vec<Aabb> = indices
+            .iter()
+            .map(|triangle| {
+                Aabb::from_points(triangle.iter().map(|&index| positions[index as usize]))
+            })
+            .collect();
+        let bounds = triangles
+            .iter()
+            .fold(Aabb::empty(), |bounds, triangle| bounds.union(triangle));
+
+        Ok(TriangleMesh {
+            positions,
+            normals: normals
+                .into_iter()
+                .map(|normal| normal.normalize())
+                .collect(),
+            texcoords: Vec::new(),
+            indices,
+            bvh: Bvh::build(&triangles),
+            bounds,
+        })
+    }
+
+    /// Adds texture coordinates to the mesh, one for every vertex, which are interpolated and
+    /// returned as the uv of a hit instead of the barycentric coordinates.
+    /// Passing an empty list removes the texture coordinates.
+    pub fn with_texcoords(self, texcoords: Vec<Vector2<f32>>) -> Result<TriangleMesh, String> {
+        if !texcoords.is_empty() && texcoords.len() != self.positions.len() {
+            return Err(format!(
+                "The mesh has {} vertices but {} texture coordinates",
+                self.positions.len(),
+                texcoords.len()
+            ));
+        }
+
+        Ok(TriangleMesh { texcoords, ..self })
+    }
+
+    /// The number of triangles in the mesh
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Returns true if the mesh has no triangles
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// The vertices of a single triangle
+    fn vertices(&self, triangle: usize) -> [Point3<f32>; 3] {
+        let [a, b, c] = self.indices[triangle];
+        [
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        ]
+    }
+
+    /// Intersection with a single triangle in the mesh
+    fn triangle_intersection(&self, triangle: usize, ray: &rays::Ray) -> Option<Hit> {
+        let [a, b, c] = self.vertices(triangle);
+        let (t, uv) = moller_trumbore(ray, a, b, c)?;
+
+        let [i, j, k] = self.indices[triangle];
+        let normal = if self.normals.is_empty() {
+            (b - a).cross(c - a).normalize()
+        } else {
+            interpolate(
+                [
+                    self.normals[i as usize],
+                    self.normals[j as usize],
+                    self.normals[k as usize],
+                ],
+                uv,
+            )
+        };
+
+        let uv = if self.texcoords.is_empty() {
+            uv
+        } else {
+            (1. - uv.x - uv.y) * self.texcoords[i as usize]
+                + uv.x * self.texcoords[j as usize]
+                + uv.y * self.texcoords[k as usize]
+        };
+
+        Some(Hit {
+            t,
+            normal: Some(normal),
+            uv: Some(uv),
+        })
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        self.bvh
+            .closest(ray, |triangle| self.triangle_intersection(triangle, ray))
+            .map(|(_, hit)| hit)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // An empty mesh has an empty box, which is not the same as no bounds at all
+        Some(self.bounds)
+    }
+}
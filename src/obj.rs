@@ -0,0 +1,577 @@
+//! Loading of Wavefront OBJ models and their MTL material libraries
+
+extern crate cgmath;
+
+use super::mesh::TriangleMesh;
+use cgmath::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Something that went wrong while reading a model, with the file and line it happened on
+#[derive(Debug)]
+pub struct ObjError {
+    pub file: String,
+    /// The line number, starting at 1. Zero if the error is not about a specific line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// A material from an MTL file
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Ka`
+    pub ambient: Vector3<f32>,
+    /// `Kd`
+    pub diffuse: Vector3<f32>,
+    /// `Ks`
+    pub specular: Vector3<f32>,
+    /// `Ke`
+    pub emission: Vector3<f32>,
+    /// `Ns`
+    pub shininess: f32,
+    /// `d`, or one minus `Tr`
+    pub dissolve: f32,
+    /// `Ni`
+    pub optical_density: f32,
+    /// `illum`
+    pub illumination: u32,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: name.to_string(),
+            ambient: Vector3::new(0., 0., 0.),
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            specular: Vector3::new(0., 0., 0.),
+            emission: Vector3::new(0., 0., 0.),
+            shininess: 0.,
+            dissolve: 1.,
+            optical_density: 1.,
+            illumination: 1,
+        }
+    }
+}
+
+/// A part of a model with a single material
+pub struct ObjMesh {
+    /// The name of the object (`o`) and group (`g`) the mesh belongs to, separated by a slash
+    pub name: String,
+    /// The name of the material, which can be looked up in `ObjModel::materials`
+    pub material: Option<String>,
+    pub mesh: TriangleMesh,
+}
+
+/// A model loaded from an OBJ file
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: HashMap<String, MtlMaterial>,
+    /// Problems that did not stop the model from loading, like a missing material library.
+    /// The meshes they affect use the default material.
+    pub warnings: Vec<ObjError>,
+}
+
+/// Loads an OBJ file, and the MTL files it refers to.
+/// The MTL files are looked up relative to the directory of the OBJ file, and any warnings are
+/// printed.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<ObjModel, ObjError> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let source = read(path)?;
+    let model = parse(&path.display().to_string(), &source, |name| {
+        let mtl = directory.join(name);
+        let source = read(&mtl)?;
+        parse_mtl(&mtl.display().to_string(), &source)
+    })?;
+    for warning in &model.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(model)
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|e| ObjError {
+        file: path.display().to_string(),
+        line: 0,
+        message: e.to_string(),
+    })
+}
+
+/// Parses the contents of an OBJ file. `file` is only used in error messages, and `mtllib` is
+/// called with the name of every material library the model refers to.
+/// A material library that cannot be loaded, or a material that is not defined, is only a
+/// warning, and the meshes using it get the default material.
+pub fn parse<F>(file: &str, source: &str, mut mtllib: F) -> Result<ObjModel, ObjError>
+where
+    F: FnMut(&str) -> Result<Vec<MtlMaterial>, ObjError>,
+{
+    let mut builder = Builder::new();
+    let mut materials = HashMap::new();
+    let mut warnings = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let mut line = Line::new(file, number + 1, line);
+        let keyword = match line.keyword() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => {
+                let position = line.point()?;
+                builder.positions.push(position);
+            }
+            "vn" => {
+                let normal = line.vector()?;
+                builder.normals.push(normal);
+            }
+            "vt" => {
+                let u = line.float()?;
+                let v = line.optional_float()?.unwrap_or(0.);
+                builder.texcoords.push(Vector2::new(u, v));
+            }
+            "f" => {
+                let mut vertices = Vec::new();
+                while let Some(word) = line.next() {
+                    vertices.push(builder.vertex(&line, word)?);
+                }
+                if vertices.len() < 3 {
+                    return Err(line.error("a face needs at least three vertices"));
+                }
+                builder.face(&vertices);
+            }
+            "o" => {
+                builder.finish_mesh(&line)?;
+                builder.object = line.rest().to_string();
+            }
+            "g" => {
+                builder.finish_mesh(&line)?;
+                builder.group = line.rest().to_string();
+            }
+            "usemtl" => {
+                builder.finish_mesh(&line)?;
+                let name = line.name()?;
+                builder.material = if materials.contains_key(name) {
+                    Some(name.to_string())
+                } else {
+                    warnings.push(line.error(&format!(
+                        "the material '{}' is not defined, using the default material",
+                        name
+                    )));
+                    None
+                };
+            }
+            "mtllib" => {
+                while let Some(name) = line.next() {
+                    match mtllib(name) {
+                        Ok(library) => {
+                            for material in library {
+                                materials.insert(material.name.clone(), material);
+                            }
+                        }
+                        Err(e) => warnings.push(line.error(&format!(
+                            "could not load the material library '{}': {}",
+                            name, e
+                        ))),
+                    }
+                }
+            }
+            // Smoothing groups, lines, points and the like do not matter for ray-tracing
+            _ => {}
+        }
+    }
+
+    builder.finish_mesh(&Line::new(file, 0, ""))?;
+
+    Ok(ObjModel {
+        meshes: builder.meshes,
+        materials,
+        warnings,
+    })
+}
+
+/// Parses the contents of an MTL file. `file` is only used in error messages.
+pub fn parse_mtl(file: &str, source: &str) -> Result<Vec<MtlMaterial>, ObjError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let mut line = Line::new(file, number + 1, line);
+        let keyword = match line.keyword() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = line.name()?;
+            materials.push(MtlMaterial::new(name));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => {
+                return Err(line.error(&format!(
+                    "'{}' has to come after a 'newmtl' statement",
+                    keyword
+                )))
+            }
+        };
+
+        match keyword {
+            "Ka" => material.ambient = line.vector()?,
+            "Kd" => material.diffuse = line.vector()?,
+            "Ks" => material.specular = line.vector()?,
+            "Ke" => material.emission = line.vector()?,
+            "Ns" => material.shininess = line.float()?,
+            "d" => material.dissolve = line.float()?,
+            "Tr" => material.dissolve = 1. - line.float()?,
+            "Ni" => material.optical_density = line.float()?,
+            "illum" => {
+                let word = line.word()?;
+                material.illumination = word.parse().map_err(|_| {
+                    line.error(&format!("expected an illumination model, found '{}'", word))
+                })?;
+            }
+            // Texture maps and the like are not supported
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+/// A single line of an OBJ or MTL file, split into words
+struct Line<'a> {
+    file: &'a str,
+    number: usize,
+    content: &'a str,
+    words: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Line<'a> {
+    fn new(file: &'a str, number: usize, content: &'a str) -> Line<'a> {
+        let content = match content.find('#') {
+            Some(comment) => &content[..comment],
+            None => content,
+        };
+
+        Line {
+            file,
+            number,
+            content,
+            words: content.split_whitespace(),
+        }
+    }
+
+    fn error(&self, message: &str) -> ObjError {
+        ObjError {
+            file: self.file.to_string(),
+            line: self.number,
+            message: message.to_string(),
+        }
+    }
+
+    fn keyword(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    /// Everything after the keyword
+    fn rest(&self) -> &'a str {
+        let trimmed = self.content.trim_start();
+        let keyword_end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        trimmed[keyword_end..].trim()
+    }
+
+    fn word(&mut self) -> Result<&'a str, ObjError> {
+        match self.words.next() {
+            Some(word) => Ok(word),
+            None => Err(self.error("unexpected end of line")),
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, ObjError> {
+        let name = self.rest();
+        if name.is_empty() {
+            Err(self.error("expected a name"))
+        } else {
+            Ok(name)
+        }
+    }
+
+    fn float(&mut self) -> Result<f32, ObjError> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| self.error(&format!("expected a number, found '{}'", word)))
+    }
+
+    fn optional_float(&mut self) -> Result<Option<f32>, ObjError> {
+        match self.words.next() {
+            Some(word) => word
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error(&format!("expected a number, found '{}'", word))),
+            None => Ok(None),
+        }
+    }
+
+    fn vector(&mut self) -> Result<Vector3<f32>, ObjError> {
+        Ok(Vector3::new(self.float()?, self.float()?, self.float()?))
+    }
+
+    fn point(&mut self) -> Result<Point3<f32>, ObjError> {
+        Ok(Point3::new(self.float()?, self.float()?, self.float()?))
+    }
+}
+
+/// A vertex of a face, as zero-based indices into the position, texture coordinate and normal
+/// lists of the file
+type Vertex = (usize, Option<usize>, Option<usize>);
+
+/// Keeps track of the state while reading an OBJ file
+struct Builder {
+    positions: Vec<Point3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    texcoords: Vec<Vector2<f32>>,
+
+    object: String,
+    group: String,
+    material: Option<String>,
+
+    /// The vertices of the mesh that is currently being read, and where they are in the mesh
+    vertices: Vec<Vertex>,
+    lookup: HashMap<Vertex, u32>,
+    indices: Vec<[u32; 3]>,
+
+    meshes: Vec<ObjMesh>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            object: String::new(),
+            group: String::new(),
+            material: None,
+            vertices: Vec::new(),
+            lookup: HashMap::new(),
+            indices: Vec::new(),
+            meshes: Vec::new(),
+        }
+    }
+
+    /// Resolves a one-based or negative (relative to the end) index
+    fn index(line: &Line, word: &str, what: &str, len: usize) -> Result<usize, ObjError> {
+        let index: i64 = word
+            .parse()
+            .map_err(|_| line.error(&format!("expected a {} index, found '{}'", what, word)))?;
+
+        let resolved = if index < 0 {
+            len as i64 + index
+        } else {
+            index - 1
+        };
+
+        if index == 0 || resolved < 0 || resolved >= len as i64 {
+            Err(line.error(&format!(
+                "{} index {} is out of bounds, there are {} so far",
+                what, index, len
+            )))
+        } else {
+            Ok(resolved as usize)
+        }
+    }
+
+    /// Reads a vertex on the form `v`, `v/vt`, `v//vn` or `v/vt/vn`
+    fn vertex(&mut self, line: &Line, word: &str) -> Result<u32, ObjError> {
+        let mut parts = word.split('/');
+
+        let position = Builder::index(
+            line,
+            parts.next().unwrap_or(""),
+            "position",
+            self.positions.len(),
+        )?;
+        let texcoord = match parts.next() {
+            Some("") | None => None,
+            Some(part) => Some(Builder::index(
+                line,
+                part,
+                "texture coordinate",
+                self.texcoords.len(),
+            )?),
+        };
+        let normal = match parts.next() {
+            Some("") | None => None,
+            Some(part) => Some(Builder::index(line, part, "normal", self.normals.len())?),
+        };
+        if parts.next().is_some() {
+            return Err(line.error(&format!("malformed vertex '{}'", word)));
+        }
+
+        let vertex = (position, texcoord, normal);
+        let vertices = &mut self.vertices;
+        Ok(*self.lookup.entry(vertex).or_insert_with(|| {
+            vertices.push(vertex);
+            vertices.len() as u32 - 1
+        }))
+    }
+
+    /// Adds a polygon, split into a fan of triangles
+    fn face(&mut self, vertices: &[u32]) {
+        for i in 1..vertices.len() - 1 {
+            self.indices
+                .push([vertices[0], vertices[i], vertices[i + 1]]);
+        }
+    }
+
+    /// Makes a mesh out of the faces read since the last mesh
+    fn finish_mesh(&mut self, line: &Line) -> Result<(), ObjError> {
+        if self.indices.is_empty() {
+            return Ok(());
+        }
+
+        let positions = self
+            .vertices
+            .iter()
+            .map(|&(position, _, _)| self.positions[position])
+            .collect();
+
+        // A mesh either has normals and texture coordinates for every vertex or for none
+        let normals = if self.vertices.iter().all(|&(_, _, normal)| normal.is_some()) {
+            self.vertices
+                .iter()
+                .filter_map(|&(_, _, normal)| normal.map(|normal| self.normals[normal]))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let texcoords = if self
+            .vertices
+            .iter()
+            .all(|&(_, texcoord, _)| texcoord.is_some())
+        {
+            self.vertices
+                .iter()
+                .filter_map(|&(_, texcoord, _)| texcoord.map(|texcoord| self.texcoords[texcoord]))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let indices = std::mem::take(&mut self.indices);
+        let mesh = TriangleMesh::new(positions, normals, indices)
+            .and_then(|mesh| mesh.with_texcoords(texcoords))
+            .map_err(|e| line.error(&e))?;
+
+        let name = match (self.object.is_empty(), self.group.is_empty()) {
+            (false, false) => format!("{}/{}", self.object, self.group),
+            (false, true) => self.object.clone(),
+            (true, _) => self.group.clone(),
+        };
+
+        self.meshes.push(ObjMesh {
+            name,
+            material: self.material.clone(),
+            mesh,
+        });
+
+        self.vertices.clear();
+        self.lookup.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Shape;
+
+    fn no_mtllib(name: &str) -> Result<Vec<MtlMaterial>, ObjError> {
+        Err(ObjError {
+            file: name.to_string(),
+            line: 0,
+            message: "not found".to_string(),
+        })
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let source = "v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n";
+        let model = parse("test.obj", source, no_mtllib).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        let bounds = model.meshes[0].mesh.bounds().unwrap();
+        assert_eq!(bounds.min, Point3::new(0., 0., 0.));
+        assert_eq!(bounds.max, Point3::new(1., 1., 0.));
+    }
+
+    #[test]
+    fn polygons_are_split_into_triangles() {
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\nf 1 2 3 4\nf 1 3 4 5 2\n";
+        let model = parse("test.obj", source, no_mtllib).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].mesh.len(), 2 + 3);
+    }
+
+    #[test]
+    fn errors_have_the_line_number() {
+        let source = "v 0 0 0\nv 1 0 0\n\n# a comment\nf 1 2 3\n";
+        let error = parse("test.obj", source, no_mtllib).err().unwrap();
+
+        assert_eq!(error.file, "test.obj");
+        assert_eq!(error.line, 5);
+        assert_eq!(
+            error.to_string(),
+            "test.obj:5: position index 3 is out of bounds, there are 2 so far"
+        );
+    }
+
+    #[test]
+    fn missing_materials_are_warnings() {
+        let source = "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+        let model = parse("test.obj", source, no_mtllib).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].material, None);
+        let lines: Vec<usize> = model.warnings.iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, vec![1, 5]);
+    }
+
+    #[test]
+    fn materials_come_from_the_library() {
+        let source = "mtllib red.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+        let model = parse("test.obj", source, |name| {
+            parse_mtl(name, "newmtl red\nKd 1 0 0\n")
+        })
+        .unwrap();
+
+        assert!(model.warnings.is_empty());
+        assert_eq!(model.meshes[0].material, Some("red".to_string()));
+        assert_eq!(model.materials["red"].diffuse, Vector3::new(1., 0., 0.));
+    }
+}
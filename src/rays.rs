@@ -2,14 +2,21 @@
 
 extern crate cgmath;
 
-use super::render;
-use super::shapes;
-use super::shapes::Material;
+use super::light::Light;
+use super::material::Material;
+use super::render::Rgb;
+use super::sampling::{cosine_hemisphere, Rng};
+use super::scene::{Integrator, Scene};
+use super::shapes::Hit;
 use cgmath::*;
 
-fn sigmoid(a: f32) -> f32 {
-    1. / (1. + (-a).exp())
-}
+use std::f32::consts::PI;
+
+/// How strongly the air around a light source glows
+const GLOW: f32 = 0.02;
+
+/// The most times a path may bounce, even if the russian roulette lets it live
+const MAX_BOUNCES: u32 = 64;
 
 fn max(a: f32, b: f32) -> f32 {
     if a > b {
@@ -19,19 +26,11 @@ fn max(a: f32, b: f32) -> f32 {
     }
 }
 
-fn min(a: f32, b: f32) -> f32 {
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
-
 /// A single ray from the camera through a pixel
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     pub origin: Point3<f32>,
     pub direction: Vector3<f32>,
-    lights: Vec<Point3<f32>>,
 }
 
 /// Contains some variables common for all rays
@@ -46,7 +45,15 @@ pub struct CamDir {
 impl CamDir {
     /// Returns the uv, witch is needed to calculate whitch way a ray should go.
     pub fn uv(x: usize, y: usize, w: usize, h: usize) -> Vector2<f32> {
-        let mut uv = Vector2::new((x as f32 + 0.5) / w as f32 - 0.5, (y as f32 + 0.5) / h as f32 - 0.5);
+        CamDir::uv_offset(x, y, w, h, Vector2::new(0.5, 0.5))
+    }
+
+    /// The uv of a point inside a pixel, where the offset goes from 0 to 1 across the pixel
+    pub fn uv_offset(x: usize, y: usize, w: usize, h: usize, offset: Vector2<f32>) -> Vector2<f32> {
+        let mut uv = Vector2::new(
+            (x as f32 + offset.x) / w as f32 - 0.5,
+            (y as f32 + offset.y) / h as f32 - 0.5,
+        );
         uv.x *= w as f32 / h as f32;
         uv.y *= -1.;
         uv
@@ -81,17 +88,8 @@ impl CamDir {
 impl Ray {
     pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
         let direction = direction.normalize();
-        let mut lights: Vec<Point3<f32>> = Vec::new();
-        lights.push(Point3::new(0., 1000., 0.));
-        //lights.push(Point3::new(5., 5., 0.));
-        //lights.push(Point3::new(0., 10., 0.));
-        lights.push(Point3::new(-5., 5., 0.));
 
-        Ray {
-            origin,
-            direction,
-            lights,
-        }
+        Ray { origin, direction }
     }
 
     /// Returns a new ray calculated from the variables in the Camera
@@ -102,144 +100,259 @@ impl Ray {
         )
     }
 
-    fn closest_material_helper(
-        materials: &mut Vec<Option<Material>>,
-        material: Material,
-    ) -> Material {
-        match materials.pop() {
-            Some(Some(new_material)) => {
-                if new_material.t < material.t {
-                    Ray::closest_material_helper(materials, new_material)
-                } else {
-                    material
-                }
-            }
-            _ => material,
+    /// The shadow, 1 if nothing is between the point and the light and 0 if something opaque is
+    fn visibility(scene: &Scene, light: &Light, point: Point3<f32>) -> f32 {
+        let (direction, distance) = light.towards(point);
+        match scene.shapes.closest(&Ray::new(point, direction)) {
+            // check to see if the light is behind the object or in front of the object, some of
+            // the light goes through transparent objects
+            Some((hit, material)) if hit.t < distance => material.transparency,
+            _ => 1.,
         }
     }
 
-    fn closest_material(&self, materials: &mut Vec<Option<Material>>) -> Option<Material> {
-        match materials.pop() {
-            Some(Some(material)) => Some(Ray::closest_material_helper(materials, material)),
-            _ => None,
+    /// Find the closest intersection point to the ray origin, and return the linear color of the
+    /// light coming back along the ray, using the integrator of the scene.
+    /// The random numbers are only used by the path tracer.
+    pub fn intersection(&self, scene: &Scene, rng: &mut Rng) -> Rgb {
+        match scene.integrator {
+            Integrator::Whitted => self.trace_depth(scene, 0),
+            Integrator::PathTracing => self.path_trace(scene, rng),
         }
     }
 
-    /// The shadow
-    fn bounce(&self, shapes: &shapes::Shapes, point: Point3<f32>) -> (f32, Option<Point3<f32>>) {
-        let mut avg = 0.;
-        for light in &self.lights {
-            avg += if let Some(material) = self.closest_material(
-                &mut shapes.shapes(&Ray::new(point, (*light - point).normalize())),
-            ) {
-                // check to see if the light is behind the object or in front of the object
-                let dist: f32 = (light - point).magnitude();
-                if material.t < dist {
-                    0.2
-                } else {
-                    1.
+    /// The color of the light coming back along the ray, found by following a random path from
+    /// surface to surface until it escapes the scene or is absorbed.
+    /// Every diffuse bounce also samples the lights of the scene directly, since they are points
+    /// and can never be hit by chance.
+    fn path_trace(&self, scene: &Scene, rng: &mut Rng) -> Rgb {
+        let mut ray = Ray::new(self.origin, self.direction);
+        let mut throughput = Vector3::new(1., 1., 1.);
+        let mut radiance = Vector3::zero();
+
+        for bounce in 0..MAX_BOUNCES {
+            let (hit, material) = match scene.shapes.closest(&ray) {
+                Some(closest) => closest,
+                None => {
+                    // The ambient light is the color of the sky
+                    radiance += throughput.mul_element_wise(scene.ambient);
+                    break;
                 }
-            } else {
-                1.
             };
+            radiance += throughput.mul_element_wise(material.emission);
+
+            let normal = match hit.normal {
+                Some(normal) => normal,
+                None => break,
+            };
+            let point = ray.origin + hit.t * ray.direction;
+
+            // Pick refraction, reflection or diffuse with the same weights the materials are
+            // blended with in the Whitted integrator
+            let choice = rng.next_f32();
+            let reflection =
+                material.transparency + (1. - material.transparency) * material.reflectivity;
+            if choice < material.transparency {
+                ray = match ray.refract(&hit, normal, material.ior) {
+                    Some((refracted, fresnel)) if rng.next_f32() >= fresnel => {
+                        throughput = throughput.mul_element_wise(material.color);
+                        refracted
+                    }
+                    _ => ray.reflect(&hit, normal),
+                };
+            } else if choice < reflection {
+                ray = ray.reflect(&hit, normal);
+            } else {
+                let normal = if normal.dot(ray.direction) > 0. {
+                    -normal
+                } else {
+                    normal
+                };
+                let albedo = material.color * material.diffuse;
+
+                // The Lambertian BRDF is albedo / pi. The bounce below samples directions with
+                // the density cos / pi, so the pi cancels there, but not for the lights.
+                let (diffuse, specular) =
+                    ray.direct_light(scene, point, Some(normal), material.shininess);
+                radiance += throughput.mul_element_wise(
+                    albedo.mul_element_wise(diffuse) / PI + specular * material.specular,
+                );
+
+                throughput = throughput.mul_element_wise(albedo);
+                ray = Ray::new(point, cosine_hemisphere(normal, rng.next_vec2()));
+            }
+
+            // Russian roulette, stop paths that carry little light early, and make up for it
+            // by weighting the paths that survive higher
+            if bounce >= 3 {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if rng.next_f32() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
         }
 
-        (sigmoid(avg), None)
+        radiance
     }
 
-    /// Find the closest intersection point to the ray origin, an return a color in HTML notation.
-    pub fn intersection(&self, shapes: &shapes::Shapes) -> u32 {
-        let (r, g, b, p) = self.col(self.closest_material(&mut shapes.shapes(&self)), &shapes);
+    /// The color of the light coming back along a ray that has been reflected `depth` times
+    fn trace_depth(&self, scene: &Scene, depth: u32) -> Rgb {
+        let closest = scene.shapes.closest(self);
+        let glow = self.light_intensity(scene, closest.as_ref().map(|(hit, _)| hit.t));
 
-        let l = if let Some(point) = p {
-            let (light, _) = self.bounce(&shapes, point);
-            light
-        } else {
-            1.
-        };
-        render::color(r * l, g * l, b * l)
-    }
+        match closest {
+            Some((hit, material)) => {
+                let mut color = self.col(scene, &hit, material);
 
-    /// Return the color of a single intersection with a shape
-    //pub fn single_intersection<S: shapes::Shape>(&self, shape: &S) -> u32 {
-    //let (r, g, b, _) = self.col(shape.intersection(&self), &shape);
-    //render::color(r, g, b)
-    //}
+                let normal = match hit.normal {
+                    Some(normal) if depth < scene.max_depth => normal,
+                    _ => return color + glow,
+                };
 
-    fn light(&self, normal: Vector3<f32>, point: Point3<f32>) -> f32 {
-        let mut avg = 0.;
-        //let mut num = 0.;
-        for light in &self.lights {
-            avg += normal.dot((light - point).normalize());
-            //num += 1.;
+                if material.reflectivity <= 0. && material.transparency <= 0. {
+                    return color + glow;
+                }
+
+                let reflected = self.reflect(&hit, normal).trace_depth(scene, depth + 1);
+                if material.reflectivity > 0. {
+                    color =
+                        color * (1. - material.reflectivity) + reflected * material.reflectivity;
+                }
+
+                if material.transparency > 0. {
+                    // Part of the light is reflected and the rest is refracted, unless all of it
+                    // is reflected inside the shape
+                    let transmitted = match self.refract(&hit, normal, material.ior) {
+                        Some((refracted, fresnel)) => {
+                            let refracted = refracted
+                                .trace_depth(scene, depth + 1)
+                                .mul_element_wise(material.color);
+                            reflected * fresnel + refracted * (1. - fresnel)
+                        }
+                        None => reflected,
+                    };
+                    color =
+                        color * (1. - material.transparency) + transmitted * material.transparency;
+                }
+
+                color + glow
+            }
+            None => glow,
         }
-        let c = sigmoid(avg);
-        if c < 0. {
-            0.
+    }
+
+    /// The ray mirrored in the surface where it hits a shape
+    pub fn reflect(&self, hit: &Hit, normal: Vector3<f32>) -> Ray {
+        let point = self.origin + hit.t * self.direction;
+        let direction = self.direction - 2. * self.direction.dot(normal) * normal;
+
+        Ray::new(point, direction)
+    }
+
+    /// The ray bent through the surface where it hits a shape with an index of refraction, and
+    /// how much of the light is reflected instead according to Schlick's approximation.
+    /// The normal points out of the shape, so rays coming from the inside go from the shape and
+    /// out into the air. Returns `None` if the light is totally reflected inside the shape.
+    pub fn refract(&self, hit: &Hit, normal: Vector3<f32>, ior: f32) -> Option<(Ray, f32)> {
+        let point = self.origin + hit.t * self.direction;
+
+        let (normal, eta) = if self.direction.dot(normal) < 0. {
+            // Entering the shape
+            (normal, 1. / ior)
         } else {
-            c
+            // Exiting the shape
+            (-normal, ior)
+        };
+
+        let cos_i = -self.direction.dot(normal);
+        let sin2_t = eta * eta * (1. - cos_i * cos_i);
+        if sin2_t > 1. {
+            return None;
         }
-    }
+        let cos_t = (1. - sin2_t).sqrt();
+        let direction = eta * self.direction + (eta * cos_i - cos_t) * normal;
 
-    /// The diffuse color around a light source
-    pub fn light_intensity(&self, shapes: &shapes::Shapes) -> f32 {
-        let mut intensity = 0.;
-        for light in &self.lights {
-            // vector that is the direction from the point on the ray closest to the light point
-            let dir = self.direction.cross(light - self.origin);
+        // Schlick uses the angle on the side with the lowest index of refraction
+        let r0 = ((1. - ior) / (1. + ior)).powi(2);
+        let cos = if eta < 1. { cos_i } else { cos_t };
+        let fresnel = r0 + (1. - r0) * (1. - cos).powi(5);
 
-            // the point on the ray that is closest to the light
-            let point = light - dir;
+        Some((Ray::new(point, direction), fresnel))
+    }
 
-            // distance from the ray origin to the point on the line closest to the light
-            let dist = (self.origin - point).magnitude();
+    /// The diffuse color around a light source, for the lights in front of `t`
+    pub fn light_intensity(&self, scene: &Scene, t: Option<f32>) -> Rgb {
+        let mut intensity = Vector3::zero();
+        for light in &scene.lights {
+            let position = match light.position() {
+                Some(position) => position,
+                None => continue,
+            };
+
+            // distance from the ray origin to the point on the ray closest to the light
+            let dist = (position - self.origin).dot(self.direction);
 
             // How far the light is from the ray
-            let h = dir.magnitude();
+            let h = (position - (self.origin + dist * self.direction)).magnitude();
 
             // If something is between the closest point on the ray and the ray origin, do not add
             // some intensity
-            intensity += if let Some(material) = self.closest_material(&mut shapes.shapes(&self)) {
-                if material.t >= dist {
-                    1. / (h * h)
-                } else {
-                    0.
-                }
-            } else {
-                // If nothing is in the way
-                1. / (h * h)
+            if dist > 0. && t.is_none_or(|t| t >= dist) {
+                intensity += light.power() * (GLOW / (h * h));
             }
         }
-        // Sigmoid function to add all the light sources
-        sigmoid(2. * intensity - 2.)
+        intensity
     }
 
-    /// Returns the color of a material
-    pub fn col(
+    /// The light from the lights of the scene arriving at a point, for diffuse reflection and
+    /// for specular highlights using the Phong reflection model
+    fn direct_light(
         &self,
-        material: Option<Material>,
-        shapes: &shapes::Shapes,
-    ) -> (f32, f32, f32, Option<Point3<f32>>) {
-        let c = self.light_intensity(&shapes);
-        match material {
-            Some(material) => {
-                let p = self.origin + material.t * self.direction;
-
-                match material.normal {
-                    Some(normal) => {
-                        let c = self.light(normal, p) + c;
-
-                        (c, c, c, Some(p))
-                    }
-                    None => (
-                        p.x.fract().abs() * c,
-                        p.y.fract().abs() * c,
-                        p.z.fract().abs() * c,
-                        Some(p),
-                    ),
+        scene: &Scene,
+        p: Point3<f32>,
+        normal: Option<Vector3<f32>>,
+        shininess: f32,
+    ) -> (Rgb, Rgb) {
+        let mut diffuse = Vector3::zero();
+        let mut specular = Vector3::zero();
+        for light in &scene.lights {
+            let radiance = light.radiance(p);
+            if radiance == Vector3::zero() {
+                continue;
+            }
+            let radiance = radiance * Ray::visibility(scene, light, p);
+            let (to_light, _) = light.towards(p);
+
+            match normal {
+                Some(normal) => {
+                    diffuse += radiance * max(normal.dot(to_light), 0.);
+
+                    let reflected = 2. * normal.dot(to_light) * normal - to_light;
+                    specular += radiance * max(reflected.dot(-self.direction), 0.).powf(shininess);
                 }
+                None => diffuse += radiance,
             }
-            None => (c, c, c, None),
         }
+
+        (diffuse, specular)
+    }
+
+    /// Returns the color of the point where the ray hits a shape
+    pub fn col(&self, scene: &Scene, hit: &Hit, material: &Material) -> Rgb {
+        let p = self.origin + hit.t * self.direction;
+
+        let (diffuse, specular) = self.direct_light(scene, p, hit.normal, material.shininess);
+        let diffuse = diffuse + scene.ambient;
+
+        let color = match hit.normal {
+            Some(_) => material.color,
+            None => Vector3::new(p.x.fract().abs(), p.y.fract().abs(), p.z.fract().abs())
+                .mul_element_wise(material.color),
+        };
+
+        color.mul_element_wise(diffuse) * material.diffuse
+            + specular * material.specular
+            + material.emission
     }
 }
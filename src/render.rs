@@ -1,12 +1,16 @@
 //! Stuff to do with rendering to screen
 
+use cgmath::Vector3;
+use rayon::prelude::*;
 use sdl2::video::WindowSurfaceRef;
 
-pub struct Pixels {
-    pixels: Vec<u32>,
-    w: usize,
-    h: usize,
-}
+use super::rays::{CamDir, Ray};
+use super::sampling::{Rng, Sampler};
+use super::scene::Scene;
+use super::tonemap::ToneMap;
+
+/// A linear rgb color, where each channel is usually between 0 and 1
+pub type Rgb = Vector3<f32>;
 
 fn cap(c: f32, min: f32, max: f32) -> f32 {
     if c > max {
@@ -19,7 +23,8 @@ fn cap(c: f32, min: f32, max: f32) -> f32 {
     c
 }
 
-/// Rurn rgb (float between 0 and 1) to a unsigned interger kind of like HTML notation
+/// Rurn rgb (float between 0 and 1) to a unsigned interger kind of like HTML notation.
+/// Only used at the very end, on colors that are already tone mapped.
 pub fn color(r: f32, g: f32, b: f32) -> u32 {
     let r = cap(r, 0., 1.);
     let g = cap(g, 0., 1.);
@@ -34,50 +39,154 @@ pub fn color(r: f32, g: f32, b: f32) -> u32 {
     color
 }
 
-impl Pixels {
-    pub fn new(w: usize, h: usize) -> Pixels {
-        let mut pixels: Vec<u32> = Vec::with_capacity(w * h);
-        for _ in 0..(w * h) {
-            pixels.push(0);
+/// The image being rendered, as linear colors without any limit on how bright they are.
+/// Every pixel adds up its samples over several frames, so the image gets less noisy the longer
+/// the camera stays still.
+pub struct Film {
+    pixels: Vec<(Rgb, f32)>,
+    w: usize,
+    h: usize,
+    samples: u32,
+}
+
+impl Film {
+    pub fn new(w: usize, h: usize) -> Film {
+        Film {
+            pixels: vec![(Vector3::new(0., 0., 0.), 0.); w * h],
+            w,
+            h,
+            samples: 0,
         }
+    }
+
+    /// Throws away all samples, and changes the size if needed
+    pub fn reset(&mut self, w: usize, h: usize) {
+        self.pixels.clear();
+        self.pixels.resize(w * h, (Vector3::new(0., 0., 0.), 0.));
+        self.w = w;
+        self.h = h;
+        self.samples = 0;
+    }
+
+    pub fn width(&self) -> usize {
+        self.w
+    }
+
+    pub fn height(&self) -> usize {
+        self.h
+    }
+
+    /// How many passes of samples every pixel has
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
 
-        Pixels { pixels, w, h }
+    /// The sums of the weighted samples and how many samples there are, row by row from the top
+    pub fn sums_mut(&mut self) -> &mut [(Rgb, f32)] {
+        &mut self.pixels
     }
 
-    /// Sets a pixel in the pixel array to a color
-    pub fn set_pixel(&mut self, x: usize, y: usize, color: u32) -> Result<(), &str> {
-        if x >= self.w || y > self.h {
-            return Err("Out of bouds");
+    /// Call after a pass of samples has been added to every pixel
+    pub fn finish_sample(&mut self) {
+        self.samples += 1;
+    }
+
+    /// The color of a pixel, which is the average of its weighted samples
+    pub fn get_pixel(&self, x: usize, y: usize) -> Rgb {
+        let (sum, count) = self.pixels[x + y * self.w];
+        if count > 0. {
+            sum / count
+        } else {
+            Vector3::new(0., 0., 0.)
+        }
+    }
+
+    /// Sets a pixel to a color, throwing away its samples
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) -> Result<(), &str> {
+        if x >= self.w || y >= self.h {
+            return Err("Out of bounds");
         }
-        self.pixels[x + y * self.w] = color;
+        self.pixels[x + y * self.w] = (color, 1.);
 
         Ok(())
     }
 
-    /// Copies the pixel array to a surface
-    pub fn copy_to_surface(&mut self, surface: &WindowSurfaceRef) -> () {
+    /// The colors of all pixels, row by row from the top
+    pub fn image(&self) -> Vec<Rgb> {
+        (0..self.h)
+            .flat_map(|y| (0..self.w).map(move |x| (x, y)))
+            .map(|(x, y)| self.get_pixel(x, y))
+            .collect()
+    }
+
+    /// The pixels converted to colors for the screen, row by row from the top
+    pub fn to_display(&self, tone_map: &ToneMap) -> Vec<u32> {
+        self.image()
+            .into_iter()
+            .map(|c| {
+                let c = tone_map.map(c);
+                color(c.x, c.y, c.z)
+            })
+            .collect()
+    }
+
+    /// Copies the pixels to a surface of the same size
+    pub fn copy_to_surface(&self, surface: &WindowSurfaceRef, tone_map: &ToneMap) {
         let (w, h) = surface.size();
+        let mut pixels = self.to_display(tone_map);
+        pixels.resize(w as usize * h as usize, 0);
         unsafe {
             let raw_surface = *surface.raw();
             raw_surface.pixels.copy_from(
-                self.pixels.as_mut_ptr() as *mut std::ffi::c_void,
+                pixels.as_mut_ptr() as *mut std::ffi::c_void,
                 w as usize * h as usize * 4,
             );
         }
-        ()
     }
 
     /// Sets the color of all pixels to black
-    pub fn clear(&mut self) -> () {
-        self.fill_color(0);
+    pub fn clear(&mut self) {
+        self.fill_color(Vector3::new(0., 0., 0.));
     }
 
-    /// Sets the color of all pixels to the input color
-    pub fn fill_color(&mut self, color: u32) -> () {
-        for x in 0..self.w {
-            for y in 0..self.h {
-                self.pixels[x + self.w * y] = color;
-            }
+    /// Sets the color of all pixels to the input color, throwing away their samples
+    pub fn fill_color(&mut self, color: Rgb) {
+        for pixel in &mut self.pixels {
+            *pixel = (color, 1.);
         }
+        self.samples = 0;
     }
 }
+
+/// How the image is sampled and shown, which can be changed while rendering
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub sampler: Sampler,
+    pub tone_map: ToneMap,
+}
+
+/// Adds a pass of samples to every pixel, using all cores.
+/// The frame decides the random numbers, so it should be different for every pass.
+pub fn render_pass(scene: &Scene, camdir: &CamDir, sampler: &Sampler, film: &mut Film, frame: u64) {
+    let (w, h) = (film.width(), film.height());
+    let pass = film.samples();
+
+    film.sums_mut()
+        .par_chunks_mut(w.max(1))
+        .enumerate()
+        .weight_max()
+        .for_each(|(y, row)| {
+            // Reused for every pixel of the row
+            let mut samples = Vec::with_capacity(sampler.samples as usize);
+            for (x, (sum, count)) in row.iter_mut().enumerate() {
+                let mut rng = Rng::for_pixel(x, y, frame);
+                sampler.pixel(x, y, pass, &mut rng, &mut samples);
+                for &(offset, weight) in &samples {
+                    let uv = CamDir::uv_offset(x, y, w, h, offset);
+                    *sum += Ray::from_camdir(camdir, uv).intersection(scene, &mut rng) * weight;
+                    *count += 1.;
+                }
+            }
+        });
+    film.finish_sample();
+}
@@ -0,0 +1,431 @@
+//! Random numbers and the distributions used when sampling light
+
+extern crate cgmath;
+
+use cgmath::*;
+
+use std::f32::consts::PI;
+
+/// A small and fast pseudo random number generator (PCG-XSH-RR).
+/// Every pixel gets its own generator, so the rendering is the same no matter how the work is
+/// split between threads.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator where the same seed gives the same numbers
+    pub fn new(seed: u64) -> Rng {
+        let mut rng = Rng { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// A generator for a pixel in a frame
+    pub fn for_pixel(x: usize, y: usize, frame: u64) -> Rng {
+        let seed = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+            ^ frame.wrapping_mul(0x1656_67b1_9e37_79f9);
+        Rng::new(seed)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// A number between 0 (inclusive) and 1 (exclusive)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Two numbers between 0 and 1
+    pub fn next_vec2(&mut self) -> Vector2<f32> {
+        Vector2::new(self.next_f32(), self.next_f32())
+    }
+}
+
+/// Two unit vectors perpendicular to each other and to the normal
+pub fn tangents(normal: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let up = if normal.x.abs() > 0.9 {
+        Vector3::new(0., 1., 0.)
+    } else {
+        Vector3::new(1., 0., 0.)
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent, bitangent)
+}
+
+/// A direction in the hemisphere around the normal, where directions close to the normal are
+/// more likely, with the probability density cos(theta) / pi.
+/// `u` are two uniformly distributed numbers between 0 and 1.
+pub fn cosine_hemisphere(normal: Vector3<f32>, u: Vector2<f32>) -> Vector3<f32> {
+    let r = u.x.sqrt();
+    let phi = 2. * PI * u.y;
+    let (tangent, bitangent) = tangents(normal);
+
+    (r * phi.cos() * tangent + r * phi.sin() * bitangent + (1. - u.x).max(0.).sqrt() * normal)
+        .normalize()
+}
+
+/// Where the samples are placed inside a pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    /// A regular grid, the same in every frame
+    Grid,
+    /// A grid where every sample is moved randomly inside its own cell
+    Stratified,
+    /// The Halton sequence with the bases 2 and 3, randomly shifted for every pixel
+    Halton,
+    /// The first two dimensions of the Sobol sequence, randomly scrambled for every pixel
+    Sobol,
+}
+
+impl Pattern {
+    /// The next pattern, for switching between them
+    pub fn next(self) -> Pattern {
+        match self {
+            Pattern::Grid => Pattern::Stratified,
+            Pattern::Stratified => Pattern::Halton,
+            Pattern::Halton => Pattern::Sobol,
+            Pattern::Sobol => Pattern::Grid,
+        }
+    }
+
+    /// Sample `i` of `n`, between 0 and 1 in both directions.
+    /// `pass` counts how many times the pixel has been sampled before, so the low discrepancy
+    /// sequences continue where they stopped instead of repeating. `scramble` must be the same
+    /// for every sample of a pixel.
+    pub fn point(self, i: u32, n: u32, pass: u32, scramble: u32, rng: &mut Rng) -> Vector2<f32> {
+        match self {
+            Pattern::Grid | Pattern::Stratified => {
+                let jitter = if self == Pattern::Grid {
+                    Vector2::new(0.5, 0.5)
+                } else {
+                    rng.next_vec2()
+                };
+                let columns = (n as f32).sqrt().round().max(1.) as u32;
+                if columns * columns == n {
+                    Vector2::new(
+                        ((i % columns) as f32 + jitter.x) / columns as f32,
+                        ((i / columns) as f32 + jitter.y) / columns as f32,
+                    )
+                } else {
+                    // A square grid would leave cells empty, so every sample gets its own column
+                    // and its own row instead, with the rows shuffled (a Latin hypercube)
+                    Vector2::new(
+                        (i as f32 + jitter.x) / n as f32,
+                        (permute(i, n, scramble) as f32 + jitter.y) / n as f32,
+                    )
+                }
+            }
+            Pattern::Halton => {
+                let index = pass.wrapping_mul(n).wrapping_add(i).wrapping_add(1);
+                let shift = Rng::new(scramble as u64).next_vec2();
+                Vector2::new(
+                    (radical_inverse(index, 2) + shift.x).fract(),
+                    (radical_inverse(index, 3) + shift.y).fract(),
+                )
+            }
+            Pattern::Sobol => {
+                let index = pass.wrapping_mul(n).wrapping_add(i);
+                let mut scrambler = Rng::new(scramble as u64);
+                Vector2::new(
+                    to_unit(index.reverse_bits() ^ scrambler.next_u32()),
+                    to_unit(sobol(index) ^ scrambler.next_u32()),
+                )
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Pattern, String> {
+        match name {
+            "grid" => Ok(Pattern::Grid),
+            "stratified" => Ok(Pattern::Stratified),
+            "halton" => Ok(Pattern::Halton),
+            "sobol" => Ok(Pattern::Sobol),
+            _ => Err(format!("Unknown sample pattern {}", name)),
+        }
+    }
+}
+
+/// Where `i` ends up in a random order of the numbers below `n`, the same order for the same
+/// seed. The order is never stored: `i` is hashed within the smallest power of two above `n`
+/// until it lands below `n`, as in Kensler's correlated multi-jittered sampling.
+fn permute(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut mask = n.wrapping_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < n {
+            return (i + seed % n) % n;
+        }
+    }
+}
+
+/// Turns the bits of a number into a fraction between 0 and 1
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// The digits of the index in a base mirrored around the decimal point
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inverse_base = 1. / base as f32;
+    let mut factor = inverse_base;
+    let mut result = 0.;
+    while index > 0 {
+        result += (index % base) as f32 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result.min(1. - f32::EPSILON)
+}
+
+/// The second dimension of the Sobol sequence as bits of a fraction
+fn sobol(mut index: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// How much a sample counts towards the color of a pixel, depending on how far from the center
+/// of the pixel it is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Every sample inside the pixel counts the same
+    Box,
+    /// Falls off linearly to the centers of the neighbouring pixels
+    Tent,
+    /// Soft and smooth, but a little blurry
+    Gaussian,
+    /// The Mitchell-Netravali filter, which keeps the image sharp
+    Mitchell,
+}
+
+impl Filter {
+    /// The next filter, for switching between them
+    pub fn next(self) -> Filter {
+        match self {
+            Filter::Box => Filter::Tent,
+            Filter::Tent => Filter::Gaussian,
+            Filter::Gaussian => Filter::Mitchell,
+            Filter::Mitchell => Filter::Box,
+        }
+    }
+
+    /// How far from the center of the pixel the filter reaches, in pixels
+    pub fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+        }
+    }
+
+    /// The weight of a sample at an offset in pixels from the center of the pixel.
+    /// The Mitchell filter can give negative weights.
+    pub fn weight(self, offset: Vector2<f32>) -> f32 {
+        self.profile(offset.x) * self.profile(offset.y)
+    }
+
+    /// The weight along one axis, since every filter is the same along both
+    fn profile(self, d: f32) -> f32 {
+        let radius = self.radius();
+        let d = d.abs();
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => (1. - d / radius).max(0.),
+            Filter::Gaussian => ((-2. * d * d).exp() - (-2. * radius * radius).exp()).max(0.),
+            Filter::Mitchell => {
+                // B = C = 1/3, as recommended by Mitchell and Netravali
+                let (b, c) = (1. / 3., 1. / 3.);
+                if d < 1. {
+                    ((12. - 9. * b - 6. * c) * d * d * d
+                        + (-18. + 12. * b + 6. * c) * d * d
+                        + (6. - 2. * b))
+                        / 6.
+                } else if d < 2. {
+                    ((-b - 6. * c) * d * d * d
+                        + (6. * b + 30. * c) * d * d
+                        + (-12. * b - 48. * c) * d
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    0.
+                }
+            }
+        }
+    }
+}
+
+/// A filter along one axis, cut into bins, for choosing offsets where the filter is strongest
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FilterTable {
+    filter: Filter,
+    /// How much of the size of the filter each bin from the center outwards has, adding up to 1
+    bins: [f32; FilterTable::BINS],
+    /// The integral of the filter along the axis, which the weights are divided by
+    integral: f32,
+}
+
+impl FilterTable {
+    const BINS: usize = 32;
+
+    fn new(filter: Filter) -> FilterTable {
+        let width = filter.radius() / FilterTable::BINS as f32;
+        let mut bins = [0.; FilterTable::BINS];
+        let mut integral = 0.;
+        for (j, bin) in bins.iter_mut().enumerate() {
+            let weight = filter.profile((j as f32 + 0.5) * width);
+            *bin = weight.abs() * width;
+            integral += 2. * weight * width;
+        }
+        let total: f32 = bins.iter().sum();
+        for bin in &mut bins {
+            *bin /= total;
+        }
+
+        FilterTable {
+            filter,
+            bins,
+            integral,
+        }
+    }
+
+    /// An offset from the center for a number between 0 and 1, and its weight, which is 1 on
+    /// average and negative where the filter is
+    fn sample(&self, u: f32) -> (f32, f32) {
+        // The first half of the numbers go left and the second half right
+        let side = if u < 0.5 { -1. } else { 1. };
+        let mut u = (2. * u - 1.).abs().min(1. - f32::EPSILON);
+
+        let width = self.filter.radius() / FilterTable::BINS as f32;
+        let mut j = 0;
+        while j + 1 < FilterTable::BINS && u >= self.bins[j] {
+            u -= self.bins[j];
+            j += 1;
+        }
+        let d = (j as f32 + (u / self.bins[j]).min(1.)) * width;
+
+        // The weight divided by the probability of the offset, where the bin is twice as likely
+        // as its share since both sides are sampled
+        let density = self.bins[j] / (2. * width);
+        (side * d, self.filter.profile(d) / (density * self.integral))
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Filter, String> {
+        match name {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("Unknown filter {}", name)),
+        }
+    }
+}
+
+/// Chooses where to shoot the rays of a pixel, and how much each of them counts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampler {
+    pub pattern: Pattern,
+    /// How many samples every pixel gets in each pass
+    pub samples: u32,
+    /// The filter, only changed with `set_filter` so the table is made once for all pixels
+    table: FilterTable,
+}
+
+impl Default for Sampler {
+    /// One random sample inside each pixel
+    fn default() -> Sampler {
+        Sampler::new(Pattern::Stratified, Filter::Box, 1)
+    }
+}
+
+impl Sampler {
+    pub fn new(pattern: Pattern, filter: Filter, samples: u32) -> Sampler {
+        Sampler {
+            pattern,
+            samples: samples.max(1),
+            table: FilterTable::new(filter),
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.table.filter
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.table = FilterTable::new(filter);
+    }
+
+    /// The samples of a pixel in a pass, as offsets from the top left corner of the pixel in
+    /// pixels together with their weights, put in `samples` in place of what was there. The
+    /// samples are spread over the whole filter, more of them where the filter is strong, so
+    /// they can land outside the pixel. The weights are 1 on average, so the color of the pixel
+    /// is the sum of the weighted samples divided by the number of samples, and a negative
+    /// weight never cancels out the rest.
+    pub fn pixel(
+        &self,
+        x: usize,
+        y: usize,
+        pass: u32,
+        rng: &mut Rng,
+        samples: &mut Vec<(Vector2<f32>, f32)>,
+    ) {
+        let scramble = Rng::for_pixel(x, y, 0).next_u32();
+        samples.clear();
+        samples.extend((0..self.samples).map(|i| {
+            let point = self.pattern.point(i, self.samples, pass, scramble, rng);
+            let (dx, wx) = self.table.sample(point.x);
+            let (dy, wy) = self.table.sample(point.y);
+            (Vector2::new(dx + 0.5, dy + 0.5), wx * wy)
+        }));
+    }
+}
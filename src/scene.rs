@@ -0,0 +1,58 @@
+//! Everything a ray needs to know about the world to find its color
+
+extern crate cgmath;
+
+use super::light::Light;
+use super::render::Rgb;
+use super::shapes::Shapes;
+use cgmath::*;
+
+/// How the color of a ray is found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    /// Direct light, shadows, mirror reflections and refraction.
+    /// Fast, but the light never bounces off diffuse surfaces.
+    Whitted,
+    /// Follows random paths of light through the scene, which gives global illumination but
+    /// needs many samples for each pixel to not be noisy
+    PathTracing,
+}
+
+impl Integrator {
+    /// The other integrator
+    pub fn toggle(self) -> Integrator {
+        match self {
+            Integrator::Whitted => Integrator::PathTracing,
+            Integrator::PathTracing => Integrator::Whitted,
+        }
+    }
+}
+
+/// The shapes and the lights of the world
+pub struct Scene {
+    pub shapes: Shapes,
+    pub lights: Vec<Light>,
+    /// Light that reaches every point, even in the shadows
+    pub ambient: Rgb,
+    /// How many times a ray may bounce off reflective surfaces
+    pub max_depth: u32,
+    pub integrator: Integrator,
+}
+
+impl Scene {
+    /// A scene with some shapes, no lights, a little ambient light and up to five reflections
+    pub fn new(shapes: Shapes) -> Scene {
+        Scene {
+            shapes,
+            lights: Vec::new(),
+            ambient: Vector3::new(0.1, 0.1, 0.1),
+            max_depth: 5,
+            integrator: Integrator::Whitted,
+        }
+    }
+
+    /// Adds a light to the scene
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+}
@@ -0,0 +1,893 @@
+//! Loading of scenes from text files
+//!
+//! Every line is a statement: a keyword, maybe some words, and then `key=value` attributes.
+//! Vectors, points and colors are written as numbers separated by commas without any spaces,
+//! and a color can also be a single number for a gray. Angles are in degrees, and `#` starts a
+//! comment.
+//!
+//! ```text
+//! camera position=0,2,-10 target=0,0,0
+//! settings ambient=0.1 max_depth=5 integrator=whitted
+//!
+//! material red color=0.9,0.1,0.1 specular=0.5 shininess=32
+//! light point position=-5,5,0 intensity=30
+//!
+//! spheroid radius=1 origin=0,0,0 dimensions=1,2,1 material=red
+//! plane normal=0,1,0 origin=0,-5.1,0
+//! ```
+//!
+//! The statements are:
+//!
+//! - `camera position=P target=P`
+//! - `settings ambient=C max_depth=N integrator=whitted|path`
+//! - `material NAME color=C diffuse=F specular=F shininess=F emission=C reflectivity=F
+//!   transparency=F ior=F`, where everything not given is the same as the default material
+//! - `light point position=P intensity=F color=C`
+//! - `light directional direction=V intensity=F color=C`
+//! - `light spot position=P direction=V angle=F softness=F intensity=F color=C`
+//! - `plane normal=V origin=P`
+//! - `sphere radius=F origin=P`
+//! - `spheroid radius=F origin=P dimensions=V`
+//! - `hyperboloid lambda=F origin=P dimensions=V`
+//! - `torus inner_radius=F tube_radius=F origin=P`
+//! - `cylinder radius=F origin=P`, which is endless along the z-axis
+//! - `cylinder radius=F from=P to=P caps=BOOL`, where the ends are closed unless `caps=false`
+//! - `cone from=P to=P radius=F to_radius=F caps=BOOL`, with the radius at each end, where
+//!   `to_radius` is 0 for a sharp tip if not given
+//! - `disk center=P normal=V radius=F`
+//! - `box min=P max=P`, with its sides along the axes
+//! - `box center=P size=V orientation=V`, turned by the angles around the x, y and z axis
+//! - `quadric ellipsoid|paraboloid|hyperboloid1|hyperboloid2|cone center=P radii=V`, along the
+//!   z-axis, where the center of a paraboloid is its vertex and of a cone its apex
+//! - `quadric general coefficients=N,...`, with the ten coefficients a to j of
+//!   ax^2 + by^2 + cz^2 + 2dxy + 2exz + 2fyz + 2gx + 2hy + 2iz + j = 0, or a 4x4 matrix
+//! - `superquadric center=P radii=V exponents=F,F`, with the exponents along and across the
+//!   z-axis
+//! - `triangle a=P b=P c=P`
+//! - `mesh file=PATH`, an OBJ model with the materials of its MTL files
+//!
+//! Every shape can have a `name=NAME` to find it by, and a `material=NAME` defined further up in
+//! the file. Shapes can be moved with `scale=V`, `rotate=V` and `translate=V`, which are done in
+//! that order. The rotation is around the x, then the y and then the z axis.
+
+extern crate cgmath;
+
+use super::light::Light;
+use super::material::Material;
+use super::mesh::Triangle;
+use super::obj::{self, ObjModel};
+use super::render::Rgb;
+use super::scene::{Integrator, Scene};
+use super::shapes::{self, Shape, Shapes};
+use super::transform::Transformed;
+use cgmath::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Something that went wrong while reading a scene, with where in the file it happened
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneError {
+    pub file: String,
+    /// The line number, starting at 1. Zero if the error is not about a specific line.
+    pub line: usize,
+    /// The column, counted in characters and starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Where the camera is and what it looks at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+}
+
+/// Everything in a scene file. The settings that are not in the file are `None`.
+#[derive(Default)]
+pub struct SceneFile {
+    pub camera: Option<Camera>,
+    pub shapes: Shapes,
+    pub lights: Vec<Light>,
+    pub ambient: Option<Rgb>,
+    pub max_depth: Option<u32>,
+    pub integrator: Option<Integrator>,
+}
+
+impl SceneFile {
+    /// Adds the meshes of a model, with the materials from its MTL files.
+    /// The meshes are named after the objects and groups of the model.
+    pub fn add_model(&mut self, model: ObjModel) {
+        for (name, shape, material) in meshes(model) {
+            self.shapes.insert(Some(name), shape, material);
+        }
+    }
+
+    /// A scene with the shapes, lights and settings of the file
+    pub fn into_scene(self) -> Scene {
+        let mut scene = Scene::new(self.shapes);
+        scene.lights = self.lights;
+        if let Some(ambient) = self.ambient {
+            scene.ambient = ambient;
+        }
+        if let Some(max_depth) = self.max_depth {
+            scene.max_depth = max_depth;
+        }
+        if let Some(integrator) = self.integrator {
+            scene.integrator = integrator;
+        }
+        scene
+    }
+}
+
+/// Loads a scene file. The models it refers to are looked up relative to the directory of the
+/// scene file.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<SceneFile, SceneError> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let source = fs::read_to_string(path).map_err(|e| SceneError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+    parse(&path.display().to_string(), &source, |name| {
+        obj::load(directory.join(name)).map_err(|e| e.to_string())
+    })
+}
+
+/// Keeps an eye on a scene file, to notice when it is saved
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watcher {
+    /// How long to wait between looking at the file
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new<P: AsRef<Path>>(path: P) -> Watcher {
+        let path = path.as_ref().to_path_buf();
+        Watcher {
+            modified: Watcher::modified(&path),
+            path,
+            checked: Instant::now(),
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Whether the file has changed since the last time this returned true. The file is only
+    /// looked at every half second, so this can be called every frame.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < Watcher::INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+
+        let modified = Watcher::modified(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Parses the contents of a scene file. `file` is only used in error messages, and `model` is
+/// called with the path of every OBJ model the scene refers to.
+pub fn parse<F>(file: &str, source: &str, mut model: F) -> Result<SceneFile, SceneError>
+where
+    F: FnMut(&str) -> Result<ObjModel, String>,
+{
+    let mut scene = SceneFile::default();
+    let mut materials: HashMap<String, Material> = HashMap::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let mut statement = match Statement::split(file, number + 1, line)? {
+            Some(statement) => statement,
+            None => continue,
+        };
+
+        let keyword = statement.keyword.text;
+        match keyword {
+            "camera" => {
+                let position = statement.point("position")?;
+                let target = statement.point("target")?;
+                scene.camera = Some(Camera {
+                    position: statement.required("position", position)?,
+                    target: statement.required("target", target)?,
+                });
+            }
+            "settings" => {
+                if let Some(ambient) = statement.color("ambient")? {
+                    scene.ambient = Some(ambient);
+                }
+                if let Some(max_depth) = statement.value("max_depth", "a whole number")? {
+                    scene.max_depth = Some(max_depth);
+                }
+                if let Some(word) = statement.take("integrator") {
+                    scene.integrator = Some(match word.text {
+                        "whitted" => Integrator::Whitted,
+                        "path" => Integrator::PathTracing,
+                        _ => {
+                            return Err(statement.error_at(
+                                word.column,
+                                &format!("expected 'whitted' or 'path', found '{}'", word.text),
+                            ))
+                        }
+                    });
+                }
+            }
+            "material" => {
+                let name = statement.word("a material name")?;
+                let material = statement.material()?;
+                materials.insert(name.text.to_string(), material);
+            }
+            "light" => {
+                let light = statement.light()?;
+                scene.lights.push(light);
+            }
+            "mesh" => {
+                let path = statement.take("file");
+                let path = statement.required("file", path)?;
+                let loaded = model(path.text).map_err(|e| statement.error_at(path.column, &e))?;
+                let material = statement.material_reference(&materials)?;
+                let transform = statement.transform()?;
+
+                let name = statement.take("name");
+
+                let meshes = meshes(loaded);
+                let single = meshes.len() == 1;
+                for (mesh_name, shape, mesh_material) in meshes {
+                    let name = match name {
+                        Some(name) if single => name.text.to_string(),
+                        Some(name) => format!("{}/{}", name.text, mesh_name),
+                        None => mesh_name,
+                    };
+                    scene.shapes.insert(
+                        Some(name),
+                        statement.place(shape, transform)?,
+                        material.unwrap_or(mesh_material),
+                    );
+                }
+            }
+            _ => {
+                let shape = statement.shape()?;
+                let material = statement.material_reference(&materials)?;
+                let transform = statement.transform()?;
+                let name = statement.take("name").map(|name| name.text.to_string());
+                let shape = statement.place(shape, transform)?;
+                scene
+                    .shapes
+                    .insert(name, shape, material.unwrap_or_default());
+            }
+        }
+
+        statement.finish()?;
+    }
+
+    Ok(scene)
+}
+
+/// The meshes of a model with their names and the materials from the MTL files
+fn meshes(model: ObjModel) -> Vec<(String, Box<dyn Shape>, Material)> {
+    let materials = model.materials;
+    model
+        .meshes
+        .into_iter()
+        .map(|mesh| {
+            let material = match &mesh.material {
+                Some(name) => Material::from(&materials[name]),
+                None => Material::default(),
+            };
+            let shape: Box<dyn Shape> = Box::new(mesh.mesh);
+            (mesh.name, shape, material)
+        })
+        .collect()
+}
+
+/// A word of a line, and the column it starts at
+#[derive(Clone, Copy, Debug)]
+struct Word<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// A single line of a scene file, split into its keyword, words and attributes
+struct Statement<'a> {
+    file: &'a str,
+    line: usize,
+    keyword: Word<'a>,
+    /// The words between the keyword and the attributes, which are taken from the front
+    words: Vec<Word<'a>>,
+    /// The keys and values of the attributes that have not been used yet
+    attributes: Vec<(Word<'a>, Word<'a>)>,
+}
+
+impl<'a> Statement<'a> {
+    /// Splits a line into words, or returns `None` if there is nothing but a comment on it
+    fn split(
+        file: &'a str,
+        line: usize,
+        content: &'a str,
+    ) -> Result<Option<Statement<'a>>, SceneError> {
+        let content = match content.find('#') {
+            Some(comment) => &content[..comment],
+            None => content,
+        };
+
+        let mut words = Vec::new();
+        let mut start = None;
+        for (index, c) in content.char_indices().chain(Some((content.len(), ' '))) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(index),
+                (Some(first), true) => {
+                    words.push(Word {
+                        text: &content[first..index],
+                        column: content[..first].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        if words.is_empty() {
+            return Ok(None);
+        }
+        let keyword = words.remove(0);
+        let mut statement = Statement {
+            file,
+            line,
+            keyword,
+            words: Vec::new(),
+            attributes: Vec::new(),
+        };
+
+        for word in words {
+            match word.text.find('=') {
+                Some(equals) => {
+                    let key = Word {
+                        text: &word.text[..equals],
+                        column: word.column,
+                    };
+                    let value = Word {
+                        text: &word.text[equals + 1..],
+                        column: word.column + word.text[..=equals].chars().count(),
+                    };
+                    if key.text.is_empty() || value.text.is_empty() {
+                        return Err(statement.error_at(
+                            word.column,
+                            &format!("expected key=value, found '{}'", word.text),
+                        ));
+                    }
+                    if statement.attributes.iter().any(|(k, _)| k.text == key.text) {
+                        return Err(statement.error_at(
+                            key.column,
+                            &format!("'{}' is given more than once", key.text),
+                        ));
+                    }
+                    statement.attributes.push((key, value));
+                }
+                None if statement.attributes.is_empty() => statement.words.push(word),
+                None => {
+                    return Err(statement.error_at(
+                        word.column,
+                        &format!("expected key=value, found '{}'", word.text),
+                    ))
+                }
+            }
+        }
+
+        Ok(Some(statement))
+    }
+
+    fn error_at(&self, column: usize, message: &str) -> SceneError {
+        SceneError {
+            file: self.file.to_string(),
+            line: self.line,
+            column,
+            message: message.to_string(),
+        }
+    }
+
+    /// An error about the whole statement
+    fn error(&self, message: &str) -> SceneError {
+        self.error_at(self.keyword.column, message)
+    }
+
+    /// Makes sure every word and attribute of the statement has been used
+    fn finish(self) -> Result<(), SceneError> {
+        if let Some(word) = self.words.first() {
+            return Err(self.error_at(
+                word.column,
+                &format!("unexpected '{}' in '{}'", word.text, self.keyword.text),
+            ));
+        }
+        if let Some((key, _)) = self.attributes.first() {
+            return Err(self.error_at(
+                key.column,
+                &format!(
+                    "'{}' does not have an attribute '{}'",
+                    self.keyword.text, key.text
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The next word after the keyword
+    fn word(&mut self, what: &str) -> Result<Word<'a>, SceneError> {
+        if self.words.is_empty() {
+            Err(self.error(&format!("'{}' needs {}", self.keyword.text, what)))
+        } else {
+            Ok(self.words.remove(0))
+        }
+    }
+
+    /// The column of the value of an attribute that has not been taken yet, or of the keyword if
+    /// there is no such attribute
+    fn column(&self, key: &str) -> usize {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k.text == key)
+            .map_or(self.keyword.column, |(_, value)| value.column)
+    }
+
+    /// Takes the value of an attribute, so it is not reported as unused
+    fn take(&mut self, key: &str) -> Option<Word<'a>> {
+        let index = self.attributes.iter().position(|(k, _)| k.text == key)?;
+        Some(self.attributes.remove(index).1)
+    }
+
+    fn required<T>(&self, key: &str, value: Option<T>) -> Result<T, SceneError> {
+        value.ok_or_else(|| self.error(&format!("'{}' needs {}=", self.keyword.text, key)))
+    }
+
+    fn value<T: std::str::FromStr>(
+        &mut self,
+        key: &str,
+        what: &str,
+    ) -> Result<Option<T>, SceneError> {
+        match self.take(key) {
+            Some(word) => word.text.parse().map(Some).map_err(|_| {
+                self.error_at(
+                    word.column,
+                    &format!("expected {} for {}, found '{}'", what, key, word.text),
+                )
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn float(&mut self, key: &str) -> Result<Option<f32>, SceneError> {
+        self.value(key, "a number")
+    }
+
+    /// Comma separated numbers
+    fn numbers(&mut self, key: &str, counts: &[usize]) -> Result<Option<Vec<f32>>, SceneError> {
+        let word = match self.take(key) {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+
+        let numbers: Result<Vec<f32>, _> = word.text.split(',').map(str::parse).collect();
+        match numbers {
+            Ok(numbers) if counts.contains(&numbers.len()) => Ok(Some(numbers)),
+            _ => {
+                let what = match counts {
+                    [1, 3] => "a number or three numbers separated by commas".to_string(),
+                    [3] => "three numbers separated by commas".to_string(),
+                    _ => {
+                        let counts: Vec<String> = counts.iter().map(|n| n.to_string()).collect();
+                        format!("{} numbers separated by commas", counts.join(" or "))
+                    }
+                };
+                Err(self.error_at(
+                    word.column,
+                    &format!("expected {} for {}, found '{}'", what, key, word.text),
+                ))
+            }
+        }
+    }
+
+    fn vector(&mut self, key: &str) -> Result<Option<Vector3<f32>>, SceneError> {
+        Ok(self
+            .numbers(key, &[3])?
+            .map(|n| Vector3::new(n[0], n[1], n[2])))
+    }
+
+    fn point(&mut self, key: &str) -> Result<Option<Point3<f32>>, SceneError> {
+        Ok(self.vector(key)?.map(Point3::from_vec))
+    }
+
+    /// Three channels, or one number for a gray
+    fn color(&mut self, key: &str) -> Result<Option<Rgb>, SceneError> {
+        Ok(self.numbers(key, &[1, 3])?.map(|n| match n.len() {
+            1 => Vector3::new(n[0], n[0], n[0]),
+            _ => Vector3::new(n[0], n[1], n[2]),
+        }))
+    }
+
+    fn required_float(&mut self, key: &str) -> Result<f32, SceneError> {
+        let value = self.float(key)?;
+        self.required(key, value)
+    }
+
+    fn required_vector(&mut self, key: &str) -> Result<Vector3<f32>, SceneError> {
+        let value = self.vector(key)?;
+        self.required(key, value)
+    }
+
+    fn required_point(&mut self, key: &str) -> Result<Point3<f32>, SceneError> {
+        let value = self.point(key)?;
+        self.required(key, value)
+    }
+
+    fn material(&mut self) -> Result<Material, SceneError> {
+        let mut material = Material::default();
+        if let Some(color) = self.color("color")? {
+            material.color = color;
+        }
+        if let Some(diffuse) = self.float("diffuse")? {
+            material.diffuse = diffuse;
+        }
+        if let Some(specular) = self.float("specular")? {
+            material.specular = specular;
+        }
+        if let Some(shininess) = self.float("shininess")? {
+            material.shininess = shininess;
+        }
+        if let Some(emission) = self.color("emission")? {
+            material.emission = emission;
+        }
+        if let Some(reflectivity) = self.float("reflectivity")? {
+            material.reflectivity = reflectivity.clamp(0., 1.);
+        }
+        if let Some(transparency) = self.float("transparency")? {
+            material.transparency = transparency.clamp(0., 1.);
+        }
+        if let Some(ior) = self.float("ior")? {
+            material.ior = ior;
+        }
+        Ok(material)
+    }
+
+    /// The material named by the `material` attribute, if there is one
+    fn material_reference(
+        &mut self,
+        materials: &HashMap<String, Material>,
+    ) -> Result<Option<Material>, SceneError> {
+        match self.take("material") {
+            Some(name) => match materials.get(name.text) {
+                Some(material) => Ok(Some(*material)),
+                None => Err(self.error_at(
+                    name.column,
+                    &format!("the material '{}' is not defined", name.text),
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn light(&mut self) -> Result<Light, SceneError> {
+        let kind = self.word("a kind of light: point, directional or spot")?;
+        let light = match kind.text {
+            "point" => {
+                let position = self.required_point("position")?;
+                Light::point(position, self.required_float("intensity")?)
+            }
+            "directional" => {
+                let direction = self.required_vector("direction")?;
+                Light::directional(direction, self.required_float("intensity")?)
+            }
+            "spot" => {
+                let position = self.required_point("position")?;
+                let direction = self.required_vector("direction")?;
+                let angle = self.required_float("angle")?;
+                let intensity = self.required_float("intensity")?;
+                let mut light = Light::spot(position, direction, Deg(angle).into(), intensity);
+                if let (Light::Spot { softness, .. }, Some(value)) =
+                    (&mut light, self.float("softness")?)
+                {
+                    *softness = value;
+                }
+                light
+            }
+            _ => {
+                return Err(self.error_at(
+                    kind.column,
+                    &format!("expected point, directional or spot, found '{}'", kind.text),
+                ))
+            }
+        };
+
+        Ok(match self.color("color")? {
+            Some(color) => light.with_color(color),
+            None => light,
+        })
+    }
+
+    fn shape(&mut self) -> Result<Box<dyn Shape>, SceneError> {
+        let ones = Vector3::new(1., 1., 1.);
+        Ok(match self.keyword.text {
+            "plane" => {
+                let normal = self.required_vector("normal")?;
+                Box::new(shapes::Plane::new(normal, self.required_point("origin")?))
+            }
+            "sphere" => {
+                let radius = self.required_float("radius")?;
+                let origin = self.required_point("origin")?;
+                Box::new(shapes::Spheroid::new(radius, origin, ones))
+            }
+            "spheroid" => {
+                let radius = self.required_float("radius")?;
+                let origin = self.required_point("origin")?;
+                let dimensions = self.vector("dimensions")?.unwrap_or(ones);
+                Box::new(shapes::Spheroid::new(radius, origin, dimensions))
+            }
+            "hyperboloid" => {
+                let lambda = self.required_float("lambda")?;
+                let origin = self.required_point("origin")?;
+                let dimensions = self.vector("dimensions")?.unwrap_or(ones);
+                Box::new(shapes::Hyperboloid::new(lambda, origin, dimensions))
+            }
+            "torus" => {
+                let inner_radius = self.required_float("inner_radius")?;
+                let tube_radius = self.required_float("tube_radius")?;
+                let origin = self.required_point("origin")?;
+                Box::new(shapes::Torus::new(inner_radius, tube_radius, origin))
+            }
+            "cylinder" => {
+                let radius = self.required_float("radius")?;
+                match self.point("from")? {
+                    Some(from) => {
+                        let to = self.ends(from)?;
+                        let caps = self.value("caps", "true or false")?.unwrap_or(true);
+                        Box::new(shapes::FiniteCylinder::new(from, to, radius, caps))
+                    }
+                    None => Box::new(shapes::Cylinder::new(
+                        radius,
+                        self.required_point("origin")?,
+                    )),
+                }
+            }
+            "cone" => {
+                let from = self.required_point("from")?;
+                let to = self.ends(from)?;
+                let radius = self.required_float("radius")?;
+                let to_radius = self.float("to_radius")?.unwrap_or(0.);
+                let caps = self.value("caps", "true or false")?.unwrap_or(true);
+                Box::new(shapes::Cone::new(from, to, radius, to_radius, caps))
+            }
+            "box" => match self.point("min")? {
+                Some(min) => Box::new(shapes::AxisBox::new(min, self.required_point("max")?)),
+                None => {
+                    let center = self.required_point("center")?;
+                    let size = self.required_vector("size")?;
+                    match self.vector("orientation")? {
+                        Some(angles) => Box::new(shapes::OrientedBox::new(
+                            center,
+                            size,
+                            Matrix3::from_angle_z(Deg(angles.z))
+                                * Matrix3::from_angle_y(Deg(angles.y))
+                                * Matrix3::from_angle_x(Deg(angles.x)),
+                        )),
+                        None => {
+                            Box::new(shapes::AxisBox::new(center - size / 2., center + size / 2.))
+                        }
+                    }
+                }
+            },
+            "quadric" => {
+                let kind = self.word(
+                    "a kind of quadric: ellipsoid, paraboloid, hyperboloid1, hyperboloid2, cone \
+                     or general",
+                )?;
+                if kind.text == "general" {
+                    let coefficients = self.numbers("coefficients", &[10, 16])?;
+                    return match self.required("coefficients", coefficients)? {
+                        c if c.len() == 10 => Ok(Box::new(shapes::Quadric::new([
+                            c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7], c[8], c[9],
+                        ]))),
+                        c => Ok(Box::new(shapes::Quadric::from_matrix(Matrix4::new(
+                            c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7], c[8], c[9], c[10],
+                            c[11], c[12], c[13], c[14], c[15],
+                        )))),
+                    };
+                }
+
+                let center = self.required_point("center")?;
+                let radii_column = self.column("radii");
+                let radii = self.required_vector("radii")?;
+                if radii.x == 0. || radii.y == 0. || radii.z == 0. {
+                    return Err(self.error_at(radii_column, "the radii cannot be zero"));
+                }
+                Box::new(match kind.text {
+                    "ellipsoid" => shapes::Quadric::ellipsoid(center, radii),
+                    "paraboloid" => shapes::Quadric::paraboloid(center, radii),
+                    "hyperboloid1" => shapes::Quadric::hyperboloid_one_sheet(center, radii),
+                    "hyperboloid2" => shapes::Quadric::hyperboloid_two_sheets(center, radii),
+                    "cone" => shapes::Quadric::cone(center, radii),
+                    other => {
+                        return Err(self.error_at(
+                            kind.column,
+                            &format!("unknown kind of quadric '{}'", other),
+                        ))
+                    }
+                })
+            }
+            "superquadric" => {
+                let center = self.required_point("center")?;
+                let radii_column = self.column("radii");
+                let radii = self.required_vector("radii")?;
+                if radii.x <= 0. || radii.y <= 0. || radii.z <= 0. {
+                    return Err(self.error_at(radii_column, "the radii have to be above zero"));
+                }
+                let exponents_column = self.column("exponents");
+                let exponents = self.numbers("exponents", &[2])?;
+                let exponents = self.required("exponents", exponents)?;
+                if exponents[0] <= 0. || exponents[1] <= 0. {
+                    return Err(
+                        self.error_at(exponents_column, "the exponents have to be above zero")
+                    );
+                }
+                Box::new(shapes::Superquadric::new(
+                    center,
+                    radii,
+                    exponents[0],
+                    exponents[1],
+                ))
+            }
+            "disk" => {
+                let center = self.required_point("center")?;
+                let normal = self.required_vector("normal")?;
+                let radius = self.required_float("radius")?;
+                Box::new(shapes::Disk::new(center, normal, radius))
+            }
+            "triangle" => {
+                let a = self.required_point("a")?;
+                let b = self.required_point("b")?;
+                Box::new(Triangle::new(a, b, self.required_point("c")?))
+            }
+            keyword => return Err(self.error(&format!("unknown statement '{}'", keyword))),
+        })
+    }
+
+    /// The `to` end of a shape that goes from one point to another, which cannot be the same
+    fn ends(&mut self, from: Point3<f32>) -> Result<Point3<f32>, SceneError> {
+        let to = self.required_point("to")?;
+        if to == from {
+            Err(self.error(&format!(
+                "the ends of a {} cannot be the same point",
+                self.keyword.text
+            )))
+        } else {
+            Ok(to)
+        }
+    }
+
+    /// The transformation given by the `scale`, `rotate` and `translate` attributes, if any,
+    /// and the column to report it at
+    fn transform(&mut self) -> Result<Option<(Matrix4<f32>, usize)>, SceneError> {
+        let scale_column = self.column("scale");
+        let scale = self.vector("scale")?;
+        let rotate = self.vector("rotate")?;
+        let translate = self.vector("translate")?;
+
+        if scale.is_none() && rotate.is_none() && translate.is_none() {
+            return Ok(None);
+        }
+
+        let mut transform = Matrix4::identity();
+        if let Some(scale) = scale {
+            if scale.x == 0. || scale.y == 0. || scale.z == 0. {
+                return Err(self.error_at(scale_column, "a shape cannot be scaled by zero"));
+            }
+            transform = Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+        }
+        if let Some(rotate) = rotate {
+            transform = Matrix4::from_angle_z(Deg(rotate.z))
+                * Matrix4::from_angle_y(Deg(rotate.y))
+                * Matrix4::from_angle_x(Deg(rotate.x))
+                * transform;
+        }
+        if let Some(translate) = translate {
+            transform = Matrix4::from_translation(translate) * transform;
+        }
+        Ok(Some((transform, scale_column)))
+    }
+
+    /// Wraps a shape in its transformation, if it has one
+    fn place(
+        &self,
+        shape: Box<dyn Shape>,
+        transform: Option<(Matrix4<f32>, usize)>,
+    ) -> Result<Box<dyn Shape>, SceneError> {
+        match transform {
+            Some((transform, column)) => match Transformed::new(shape, transform) {
+                Some(transformed) => Ok(Box::new(transformed)),
+                // Scales so small that the matrix cannot be inverted in f32
+                None => Err(self.error_at(column, "a shape cannot be scaled this small")),
+            },
+            None => Ok(shape),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_models(path: &str) -> Result<ObjModel, String> {
+        Err(format!("cannot load '{}'", path))
+    }
+
+    fn error(source: &str) -> SceneError {
+        parse("test.scene", source, no_models).err().unwrap()
+    }
+
+    #[test]
+    fn shapes_are_added() {
+        let source =
+            "# a comment\n\nsphere radius=1 origin=0,0,0\nplane normal=0,1,0 origin=0,-1,0\n";
+        let scene = parse("test.scene", source, no_models).unwrap();
+
+        assert_eq!(scene.shapes.len(), 2);
+    }
+
+    #[test]
+    fn bad_values_are_located() {
+        let error = error("camera position=0,2,-10 target=0,0,0\nsphere  radius=big\n");
+
+        assert_eq!((error.line, error.column), (2, 16));
+        assert_eq!(
+            error.to_string(),
+            "test.scene:2:16: expected a number for radius, found 'big'"
+        );
+    }
+
+    #[test]
+    fn unknown_attributes_are_located() {
+        let error = error("\nsphere radius=1 colour=red origin=0,0,0\n");
+
+        assert_eq!((error.line, error.column), (2, 17));
+        assert_eq!(
+            error.message,
+            "'sphere' does not have an attribute 'colour'"
+        );
+    }
+
+    #[test]
+    fn transformations_that_cannot_be_undone_are_located() {
+        let error = error("sphere radius=1 origin=0,0,0 scale=1e-20,1e-20,1e-20\n");
+
+        assert_eq!((error.line, error.column), (1, 36));
+        assert_eq!(error.message, "a shape cannot be scaled this small");
+    }
+}
@@ -0,0 +1,472 @@
+//! Shapes given by signed distance functions, rendered by sphere tracing.
+//!
+//! A distance function tells how far a point is from the surface of a shape, negative inside it.
+//! They are easy to combine and bend in ways that are hard to do with equations for the surface,
+//! and `Traced` turns any of them into a `Shape`.
+
+extern crate cgmath;
+
+use super::aabb::Aabb;
+use super::rays;
+use super::shapes::{Hit, Shape, MIN_T};
+use cgmath::*;
+
+use std::sync::Arc;
+
+/// A signed distance function
+pub trait Distance: Send + Sync {
+    /// The distance from the point to the surface, negative inside the shape. It may be less
+    /// than the real distance, but never more, or the tracing can step through the surface.
+    fn distance(&self, p: Point3<f32>) -> f32;
+
+    /// A box containing the whole shape, or `None` if the shape is infinitely large
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+impl<D: Distance + ?Sized> Distance for &D {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        (**self).distance(p)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+}
+
+impl<D: Distance + ?Sized> Distance for Box<D> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        (**self).distance(p)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+}
+
+impl<D: Distance + ?Sized> Distance for Arc<D> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        (**self).distance(p)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+}
+
+/// Polynomial smooth minimum, which rounds the corner where a and b are equal over a width of k
+pub fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0. {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.) / k;
+    a.min(b) - h * h * k / 4.
+}
+
+/// Polynomial smooth maximum, the counterpart of `smooth_min`
+pub fn smooth_max(a: f32, b: f32, k: f32) -> f32 {
+    -smooth_min(-a, -b, k)
+}
+
+/// A box grown by an amount in every direction
+fn padded(bounds: Aabb, amount: f32) -> Aabb {
+    let pad = Vector3::new(amount, amount, amount);
+    Aabb::new(bounds.min - pad, bounds.max + pad)
+}
+
+// {{{ PRIMITIVES
+pub struct Sphere {
+    center: Point3<f32>,
+    radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Sphere {
+        Sphere { center, radius }
+    }
+}
+
+impl Distance for Sphere {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        (p - self.center).magnitude() - self.radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+}
+
+/// A box with rounded edges and corners
+pub struct RoundedBox {
+    center: Point3<f32>,
+    /// Half the size of the box along each axis, before it is rounded
+    half_size: Vector3<f32>,
+    radius: f32,
+}
+
+impl RoundedBox {
+    /// The rounding grows the box by the radius in every direction
+    pub fn new(center: Point3<f32>, half_size: Vector3<f32>, radius: f32) -> RoundedBox {
+        RoundedBox {
+            center,
+            half_size,
+            radius,
+        }
+    }
+}
+
+/// The distance to a box at the origin with sharp edges
+fn box_distance(p: Vector3<f32>, half_size: Vector3<f32>) -> f32 {
+    let q = Vector3::new(
+        p.x.abs() - half_size.x,
+        p.y.abs() - half_size.y,
+        p.z.abs() - half_size.z,
+    );
+    let outside = Vector3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).magnitude();
+    let inside = q.x.max(q.y).max(q.z).min(0.);
+    outside + inside
+}
+
+impl Distance for RoundedBox {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        box_distance(p - self.center, self.half_size) - self.radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let extent = self.half_size + Vector3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// A torus lying in the xy-plane, like `shapes::Torus`
+pub struct Torus {
+    center: Point3<f32>,
+    inner_radius: f32,
+    tube_radius: f32,
+}
+
+impl Torus {
+    pub fn new(center: Point3<f32>, inner_radius: f32, tube_radius: f32) -> Torus {
+        Torus {
+            center,
+            inner_radius,
+            tube_radius,
+        }
+    }
+}
+
+impl Distance for Torus {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let p = p - self.center;
+        let ring = Vector2::new(p.x, p.y).magnitude() - self.inner_radius;
+        Vector2::new(ring, p.z).magnitude() - self.tube_radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let xy = self.inner_radius.abs() + self.tube_radius.abs();
+        let extent = Vector3::new(xy, xy, self.tube_radius.abs());
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// A line segment with a thickness, rounded at the ends
+pub struct Capsule {
+    a: Point3<f32>,
+    b: Point3<f32>,
+    radius: f32,
+}
+
+impl Capsule {
+    pub fn new(a: Point3<f32>, b: Point3<f32>, radius: f32) -> Capsule {
+        Capsule { a, b, radius }
+    }
+}
+
+impl Distance for Capsule {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let length = ba.magnitude2();
+        let h = if length > 0. {
+            (pa.dot(ba) / length).clamp(0., 1.)
+        } else {
+            0.
+        };
+        (pa - ba * h).magnitude() - self.radius
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(padded(Aabb::new(self.a, self.b), self.radius))
+    }
+}
+// }}}
+
+// {{{ OPERATORS
+/// The two shapes melted together, with the seam rounded over a width of k
+pub struct SmoothUnion<A: Distance, B: Distance> {
+    a: A,
+    b: B,
+    k: f32,
+}
+
+impl<A: Distance, B: Distance> SmoothUnion<A, B> {
+    pub fn new(a: A, b: B, k: f32) -> SmoothUnion<A, B> {
+        SmoothUnion { a, b, k }
+    }
+}
+
+impl<A: Distance, B: Distance> Distance for SmoothUnion<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // The smooth minimum is at most k / 4 below the minimum
+        let bounds = self.a.bounds()?.union(&self.b.bounds()?);
+        Some(padded(bounds, self.k.max(0.) / 4.))
+    }
+}
+
+/// Where the two shapes overlap, with the edge rounded over a width of k
+pub struct SmoothIntersection<A: Distance, B: Distance> {
+    a: A,
+    b: B,
+    k: f32,
+}
+
+impl<A: Distance, B: Distance> SmoothIntersection<A, B> {
+    pub fn new(a: A, b: B, k: f32) -> SmoothIntersection<A, B> {
+        SmoothIntersection { a, b, k }
+    }
+}
+
+impl<A: Distance, B: Distance> Distance for SmoothIntersection<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        smooth_max(self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match (self.a.bounds(), self.b.bounds()) {
+            (Some(a), Some(b)) => Some(a.intersection(&b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// The first shape with the second carved out of it, with the edge rounded over a width of k
+pub struct SmoothDifference<A: Distance, B: Distance> {
+    a: A,
+    b: B,
+    k: f32,
+}
+
+impl<A: Distance, B: Distance> SmoothDifference<A, B> {
+    pub fn new(a: A, b: B, k: f32) -> SmoothDifference<A, B> {
+        SmoothDifference { a, b, k }
+    }
+}
+
+impl<A: Distance, B: Distance> Distance for SmoothDifference<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        smooth_max(self.a.distance(p), -self.b.distance(p), self.k)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.a.bounds()
+    }
+}
+
+/// A shape between two others, morphing from the first at 0 to the second at 1
+pub struct Blend<A: Distance, B: Distance> {
+    a: A,
+    b: B,
+    amount: f32,
+}
+
+impl<A: Distance, B: Distance> Blend<A, B> {
+    /// The amount is kept between 0 and 1, as going past either shape would not stay inside
+    /// their bounds
+    pub fn new(a: A, b: B, amount: f32) -> Blend<A, B> {
+        Blend {
+            a,
+            b,
+            amount: amount.clamp(0., 1.),
+        }
+    }
+}
+
+impl<A: Distance, B: Distance> Distance for Blend<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let a = self.a.distance(p);
+        let b = self.b.distance(p);
+        a + (b - a) * self.amount
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // Outside both shapes both distances are positive, and so is any mix of them between
+        // 0 and 1
+        Some(self.a.bounds()?.union(&self.b.bounds()?))
+    }
+}
+// }}}
+
+/// The Menger sponge, a cube with crosses cut out of it over and over
+pub struct MengerSponge {
+    center: Point3<f32>,
+    /// Half the length of the sides of the cube
+    half_size: f32,
+    iterations: u32,
+}
+
+impl MengerSponge {
+    /// Every iteration cuts out holes a third the size of the previous ones
+    pub fn new(center: Point3<f32>, half_size: f32, iterations: u32) -> MengerSponge {
+        MengerSponge {
+            center,
+            half_size,
+            iterations,
+        }
+    }
+}
+
+impl Distance for MengerSponge {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        // The sponge fills the cube from -1 to 1 here
+        let p = (p - self.center) / self.half_size;
+        let mut d = box_distance(p, Vector3::new(1., 1., 1.));
+
+        let mut scale = 1.;
+        for _ in 0..self.iterations {
+            // Folds space so every cell of the grid is the same, centered on the origin
+            let cell = |c: f32| (c * scale).rem_euclid(2.) - 1.;
+            let a = Vector3::new(cell(p.x), cell(p.y), cell(p.z));
+            scale *= 3.;
+
+            // The cross through the middle of the cell
+            let r = Vector3::new(
+                (1. - 3. * a.x.abs()).abs(),
+                (1. - 3. * a.y.abs()).abs(),
+                (1. - 3. * a.z.abs()).abs(),
+            );
+            let cross = r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x));
+            d = d.max((cross - 1.) / scale);
+        }
+
+        d * self.half_size
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let extent = Vector3::new(self.half_size, self.half_size, self.half_size);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// A distance function as a shape, found by sphere tracing.
+///
+/// The ray steps forward by the distance to the surface, which can never overshoot it, until it
+/// is close enough to count as a hit or it has taken too many steps.
+pub struct Traced<D: Distance> {
+    distance: D,
+    max_steps: u32,
+    epsilon: f32,
+    max_distance: f32,
+}
+
+impl<D: Distance> Traced<D> {
+    /// Traces with 256 steps, and counts anything within 0.0001 of the surface as a hit
+    pub fn new(distance: D) -> Traced<D> {
+        Traced {
+            distance,
+            max_steps: 256,
+            epsilon: 1e-4,
+            max_distance: 1000.,
+        }
+    }
+
+    /// Sets how many steps a ray takes before it gives up. Shapes where rays pass close by the
+    /// surface, like fractals, need more.
+    pub fn steps(self, max_steps: u32) -> Traced<D> {
+        Traced { max_steps, ..self }
+    }
+
+    /// Sets how close to the surface counts as a hit
+    pub fn epsilon(self, epsilon: f32) -> Traced<D> {
+        Traced { epsilon, ..self }
+    }
+
+    /// Sets how far a ray is traced if the shape has no bounds
+    pub fn max_distance(self, max_distance: f32) -> Traced<D> {
+        Traced {
+            max_distance,
+            ..self
+        }
+    }
+
+    /// The distance function that is traced
+    pub fn distance(&self) -> &D {
+        &self.distance
+    }
+
+    /// The normal is the gradient of the distance, found with central differences
+    fn normal(&self, p: Point3<f32>) -> Vector3<f32> {
+        // A little wider than epsilon, so rounding errors in the distance do not show
+        let h = self.epsilon * 10.;
+        let d = |offset: Vector3<f32>| {
+            self.distance.distance(p + offset) - self.distance.distance(p - offset)
+        };
+        Vector3::new(
+            d(Vector3::new(h, 0., 0.)),
+            d(Vector3::new(0., h, 0.)),
+            d(Vector3::new(0., 0., h)),
+        )
+        .normalize()
+    }
+}
+
+impl<D: Distance> Shape for Traced<D> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        // Only the part of the ray inside the bounds has to be traced
+        let (start, end) = match self.distance.bounds() {
+            Some(bounds) => {
+                let inv_direction = Vector3::new(
+                    1. / ray.direction.x,
+                    1. / ray.direction.y,
+                    1. / ray.direction.z,
+                );
+                let (near, far) = padded(bounds, self.epsilon).slab(ray.origin, inv_direction)?;
+                (near.max(MIN_T), far)
+            }
+            None => (MIN_T, self.max_distance),
+        };
+
+        // A ray starting inside the shape, like one refracted into it, looks for the way out
+        let mut t = start;
+        let side = self
+            .distance
+            .distance(ray.origin + ray.direction * t)
+            .signum();
+        for _ in 0..self.max_steps {
+            if t > end {
+                return None;
+            }
+
+            let p = ray.origin + ray.direction * t;
+            let d = side * self.distance.distance(p);
+            if d < self.epsilon {
+                return Some(Hit {
+                    t,
+                    normal: Some(self.normal(p)),
+                    uv: None,
+                });
+            }
+            t += d;
+        }
+        None
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.distance.bounds()
+    }
+}
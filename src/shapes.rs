@@ -3,33 +3,19 @@
 extern crate cgmath;
 extern crate roots;
 
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::material::Material;
 use super::rays;
 use cgmath::*;
-use roots::find_roots_quartic;
-use roots::Roots;
-
-use std::ops::Add;
-
-macro_rules! shape_add {
-    ( $($t:ident)* ) => {
-        $(
-            impl<'a> Add<&'a Shape> for &'a $t {
-                type Output = Shapes<'a>;
-
-                fn add(self, other: &'a Shape) -> Shapes<'a> {
-                    let mut shapes: Vec<&'a Shape> = Vec::new();
-                    shapes.push(self);
-                    shapes.push(other);
-                    Shapes { shapes }
-                }
-            }
-        )*
-    }
-}
+use roots::{find_root_brent, find_roots_quartic, SimpleConvergency};
 
-shape_add!(Plane Hyperboloid Torus Cylinder Spheroid);
+use std::cmp::Ordering;
+use std::sync::{Arc, OnceLock};
 
-const MIN_T: f32 = 0.01;
+/// The shortest distance along a ray that counts as a hit, so a ray starting on a surface does
+/// not hit that same surface
+pub const MIN_T: f32 = 0.01;
 
 /// If the point on the ray is behind the camera
 /// or have values like NaN and inf
@@ -46,6 +32,105 @@ fn abc(a: f32, b: f32, c: f32) -> Option<f32> {
     }
 }
 
+/// Both solutions of a quadratic equation, the smallest first
+fn both_roots(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    let num = b * b - 4. * a * c;
+
+    if num < 0. || a == 0. {
+        None
+    } else {
+        let t1 = (-b + num.sqrt()) / (2. * a);
+        let t2 = (-b - num.sqrt()) / (2. * a);
+
+        Some((t1.min(t2), t1.max(t2)))
+    }
+}
+
+/// Where a ray is inside a shape given by f(p) < 0, when f along the ray is the quadratic
+/// at^2 + bt + c. `hit` gives the hit on the surface at a distance along the ray.
+fn quadric_spans<F: Fn(f32) -> Hit>(a: f32, b: f32, c: f32, hit: F) -> Vec<Span> {
+    let everywhere = Span {
+        enter: Hit::at(f32::NEG_INFINITY),
+        exit: Hit::at(f32::INFINITY),
+    };
+
+    if a.abs() < 1e-9 {
+        // The ray only crosses the surface once, if at all
+        return if b == 0. {
+            if c < 0. {
+                vec![everywhere]
+            } else {
+                Vec::new()
+            }
+        } else if b < 0. {
+            vec![Span {
+                enter: hit(-c / b),
+                exit: Hit::at(f32::INFINITY),
+            }]
+        } else {
+            vec![Span {
+                enter: Hit::at(f32::NEG_INFINITY),
+                exit: hit(-c / b),
+            }]
+        };
+    }
+
+    match both_roots(a, b, c) {
+        // Inside between the roots
+        Some((t1, t2)) if a > 0. => vec![Span {
+            enter: hit(t1),
+            exit: hit(t2),
+        }],
+        // Inside everywhere but between the roots
+        Some((t1, t2)) => vec![
+            Span {
+                enter: Hit::at(f32::NEG_INFINITY),
+                exit: hit(t1),
+            },
+            Span {
+                enter: hit(t2),
+                exit: Hit::at(f32::INFINITY),
+            },
+        ],
+        None if a < 0. => vec![everywhere],
+        None => Vec::new(),
+    }
+}
+
+/// Where a ray is inside a bounded shape, given every distance along the ray where it crosses
+/// the surface, sorted. Every gap between two crossings is checked with `inside` at its middle,
+/// rather than counting crossings, so a ray touching the surface or a missed crossing does not
+/// swap the inside and the outside for the rest of the ray.
+fn spans_between<I: Fn(f32) -> bool, F: Fn(f32) -> Hit>(
+    roots: &[f32],
+    inside: I,
+    hit: F,
+) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut enter = None;
+    for pair in roots.windows(2) {
+        match (enter, inside((pair[0] + pair[1]) / 2.)) {
+            (None, true) => enter = Some(pair[0]),
+            (Some(start), false) => {
+                spans.push(Span {
+                    enter: hit(start),
+                    exit: hit(pair[0]),
+                });
+                enter = None;
+            }
+            _ => {}
+        }
+    }
+    // The ray is outside past the last crossing
+    if let (Some(start), Some(&end)) = (enter, roots.last()) {
+        spans.push(Span {
+            enter: hit(start),
+            exit: hit(end),
+        });
+    }
+    spans
+}
+
 fn min_g0(a: f32, b: f32) -> Option<f32> {
     if a < MIN_T && b < MIN_T {
         None
@@ -59,60 +144,313 @@ fn min_g0(a: f32, b: f32) -> Option<f32> {
 }
 
 /// A shape is something that may intersect a ray at some point in space
-pub trait Shape: std::marker::Sync {
+pub trait Shape: Send + Sync {
     /// The intersection closest point between the shape and a ray, it it exists
-    fn intersection(&self, ray: &rays::Ray) -> Option<Material>;
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit>;
+
+    /// A box containing the whole shape, or `None` if the shape is infinitely large
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Every part of the ray that is inside the shape, sorted along the ray, including the parts
+    /// behind the origin of the ray. The normals point out of the shape.
+    /// `None` if the shape has no inside, like a triangle, so it cannot be used for
+    /// constructive solid geometry.
+    fn intervals(&self, _ray: &rays::Ray) -> Option<Vec<Span>> {
+        None
+    }
+}
+
+/// A reference to a shape is the same shape, which makes it possible to reuse one shape in
+/// several places, e.g. with different transformations.
+impl<S: Shape + ?Sized> Shape for &S {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        (**self).intersection(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        (**self).intervals(ray)
+    }
+}
+
+/// A boxed shape is the same shape, so shapes of different types can be kept together
+impl<S: Shape + ?Sized> Shape for Box<S> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        (**self).intersection(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        (**self).intervals(ray)
+    }
+}
+
+/// A shared shape is the same shape, so one shape can be used by several objects
+impl<S: Shape + ?Sized> Shape for Arc<S> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        (**self).intersection(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        (**self).intervals(ray)
+    }
+}
+
+/// The shapes of a collection sorted into a bounding volume hierarchy, and the shapes that are
+/// too large to be in it
+struct Hierarchy {
+    bvh: Bvh,
+    /// The index of the shape of each primitive in the bvh
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+/// Identifies an object in a collection of shapes. The id of an object never changes, and is
+/// not reused when the object is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId(u64);
+
+/// A shape in a collection of shapes, with its material and maybe a name
+pub struct Object {
+    pub id: ObjectId,
+    pub name: Option<String>,
+    pub shape: Box<dyn Shape>,
+    pub material: Material,
 }
 
 /// Contains some shapes that a ray can intersect with.
 /// This should be passed in to a ray, so the ray can calculate the color if the intersection
 /// point.
-pub struct Shapes<'a> {
-    shapes: Vec<&'a Shape>,
+pub struct Shapes {
+    /// Sorted by id, since new objects get higher ids and are added at the end
+    objects: Vec<Object>,
+    next_id: u64,
+    /// Built the first time a ray is traced after the shapes have changed
+    hierarchy: OnceLock<Hierarchy>,
 }
 
-impl<'a> Add<&'a Shape> for Shapes<'a> {
-    type Output = Shapes<'a>;
+impl Default for Shapes {
+    fn default() -> Self {
+        Shapes::new()
+    }
+}
 
-    fn add(self, other: &'a Shape) -> Shapes<'a> {
-        let mut shapes = Vec::new();
-        for shape in &self.shapes {
-            shapes.push(*shape);
+impl Shapes {
+    pub fn new() -> Shapes {
+        Shapes {
+            objects: Vec::new(),
+            next_id: 0,
+            hierarchy: OnceLock::new(),
         }
-        shapes.push(other);
-        Shapes { shapes }
     }
-}
 
-impl<'a> Shapes<'a> {
-    pub fn new() -> Shapes<'a> {
-        let shapes = Vec::new();
-        Shapes { shapes }
+    /// Add a shape the the collection of shapes, with the default material
+    pub fn add<S: Shape + 'static>(&mut self, shape: S) -> ObjectId {
+        self.add_with_material(shape, Material::default())
+    }
+
+    /// Add a shape with a material to the collection of shapes
+    pub fn add_with_material<S: Shape + 'static>(
+        &mut self,
+        shape: S,
+        material: Material,
+    ) -> ObjectId {
+        self.insert(None, Box::new(shape), material)
+    }
+
+    /// Add a shape with a name and a material to the collection of shapes
+    pub fn add_named<S: Shape + 'static>(
+        &mut self,
+        name: &str,
+        shape: S,
+        material: Material,
+    ) -> ObjectId {
+        self.insert(Some(name.to_string()), Box::new(shape), material)
+    }
+
+    /// Add a shape that is already boxed, maybe with a name
+    pub fn insert(
+        &mut self,
+        name: Option<String>,
+        shape: Box<dyn Shape>,
+        material: Material,
+    ) -> ObjectId {
+        let id = ObjectId(self.next_id);
+        self.next_id += 1;
+        self.objects.push(Object {
+            id,
+            name,
+            shape,
+            material,
+        });
+        self.hierarchy = OnceLock::new();
+        id
+    }
+
+    /// Takes an object out of the collection
+    pub fn remove(&mut self, id: ObjectId) -> Option<Object> {
+        let index = self.index(id)?;
+        self.hierarchy = OnceLock::new();
+        Some(self.objects.remove(index))
+    }
+
+    fn index(&self, id: ObjectId) -> Option<usize> {
+        self.objects
+            .binary_search_by_key(&id, |object| object.id)
+            .ok()
+    }
+
+    pub fn get(&self, id: ObjectId) -> Option<&Object> {
+        Some(&self.objects[self.index(id)?])
     }
 
-    /// Add a shape the the collection of shapes
-    pub fn add<S: Shape>(&mut self, shape: &'a S) {
-        self.shapes.push(shape);
+    /// The object can be changed in any way, so the hierarchy is built again afterwards
+    pub fn get_mut(&mut self, id: ObjectId) -> Option<&mut Object> {
+        let index = self.index(id)?;
+        self.hierarchy = OnceLock::new();
+        Some(&mut self.objects[index])
     }
 
-    pub fn shapes(&self, ray: &rays::Ray) -> Vec<Option<Material>> {
-        self.shapes
+    /// The first object with a name
+    pub fn find(&self, name: &str) -> Option<&Object> {
+        self.objects
             .iter()
-            .map(|x| -> Option<Material> { x.intersection(&ray) })
-            .filter(|x| -> bool {
-                match x {
-                    None => false,
-                    _ => true,
+            .find(|object| object.name.as_deref() == Some(name))
+    }
+
+    /// Changes the material of an object. Returns false if there is no such object.
+    pub fn set_material(&mut self, id: ObjectId, material: Material) -> bool {
+        match self.index(id) {
+            Some(index) => {
+                self.objects[index].material = material;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The objects, in the order they were added
+    pub fn iter(&self) -> std::slice::Iter<'_, Object> {
+        self.objects.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    fn hierarchy(&self) -> &Hierarchy {
+        self.hierarchy.get_or_init(|| {
+            let mut bounds = Vec::new();
+            let mut bounded = Vec::new();
+            let mut unbounded = Vec::new();
+            for (index, object) in self.objects.iter().enumerate() {
+                match object.shape.bounds() {
+                    // A shape with nothing in it can never be hit
+                    Some(aabb) if aabb.is_empty() => {}
+                    Some(aabb) => {
+                        bounds.push(aabb);
+                        bounded.push(index);
+                    }
+                    None => unbounded.push(index),
                 }
+            }
+
+            Hierarchy {
+                bvh: Bvh::build(&bounds),
+                bounded,
+                unbounded,
+            }
+        })
+    }
+
+    /// A box containing every shape, or `None` if any of the shapes is infinitely large
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.objects.iter().try_fold(Aabb::empty(), |aabb, object| {
+            Some(aabb.union(&object.shape.bounds()?))
+        })
+    }
+
+    /// A box containing every shape that is not infinitely large, or `None` if there are no
+    /// such shapes
+    pub fn finite_bounds(&self) -> Option<Aabb> {
+        let hierarchy = self.hierarchy();
+        hierarchy
+            .bounded
+            .iter()
+            .filter_map(|&index| self.objects[index].shape.bounds())
+            .fold(None, |bounds: Option<Aabb>, aabb| {
+                Some(bounds.map_or(aabb, |bounds| bounds.union(&aabb)))
             })
-            .collect()
+    }
+
+    /// The closest intersection between the ray and any of the shapes, and the material of the
+    /// shape that was hit
+    pub fn closest(&self, ray: &rays::Ray) -> Option<(Hit, &Material)> {
+        let hierarchy = self.hierarchy();
+
+        let closest = hierarchy
+            .bvh
+            .closest(ray, |primitive| {
+                self.objects[hierarchy.bounded[primitive]]
+                    .shape
+                    .intersection(ray)
+            })
+            .map(|(primitive, hit)| (hierarchy.bounded[primitive], hit));
+
+        hierarchy
+            .unbounded
+            .iter()
+            .filter_map(|&index| Some((index, self.objects[index].shape.intersection(ray)?)))
+            .chain(closest)
+            .min_by(|(_, a), (_, b)| a.t.partial_cmp(&b.t).unwrap_or(Ordering::Equal))
+            .map(|(index, hit)| (hit, &self.objects[index].material))
     }
 }
 
-/// The material of a point
-pub struct Material {
+/// Where a ray hits a shape
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
     pub t: f32,
     pub normal: Option<Vector3<f32>>,
+    /// Coordinates of the point on the surface, if the shape has any.
+    /// For triangles these are the barycentric coordinates of the second and third vertex,
+    /// unless the mesh has texture coordinates.
+    pub uv: Option<Vector2<f32>>,
+}
+
+impl Hit {
+    /// A hit with nothing but a distance, used for the infinitely far ends of intervals
+    pub fn at(t: f32) -> Hit {
+        Hit {
+            t,
+            normal: None,
+            uv: None,
+        }
+    }
+}
+
+/// A part of a ray that is inside a shape, from where the ray enters the shape to where it
+/// leaves it. The ends are infinitely far away if the shape is infinitely large.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub enter: Hit,
+    pub exit: Hit,
 }
 
 /// An infinetly long tube
@@ -129,25 +467,533 @@ impl Cylinder {
     }
 }
 
-impl Shape for Cylinder {
-    fn intersection(&self, ray: &rays::Ray) -> Option<Material> {
+impl Cylinder {
+    /// The coefficients of x^2 + y^2 - r^2 along the ray
+    fn coefficients(&self, ray: &rays::Ray) -> (f32, f32, f32) {
         let origin = ray.origin - self.origin;
         let sq = |x| -> f32 { x * x };
 
         let a = sq(ray.direction.x) + sq(ray.direction.y);
         let b = 2. * (origin.x * ray.direction.x + origin.y * ray.direction.y);
         let c = sq(origin.x) + sq(origin.y) - sq(self.radius);
+        (a, b, c)
+    }
 
-        match abc(a, b, c) {
-            Some(t) => {
-                let origin = Vector3::new(ray.origin.x, ray.origin.y, ray.origin.z);
-                let mut normal = origin + ray.direction * t;
-                normal.z = 0.;
-                let normal = Some(normal);
+    fn hit(&self, ray: &rays::Ray, t: f32) -> Hit {
+        // The normal is the vector from the axis to the hit point
+        let mut normal = ray.origin - self.origin + ray.direction * t;
+        normal.z = 0.;
 
-                Some(Material { t, normal })
-            }
-            None => None,
+        Hit {
+            t,
+            normal: Some(normal.normalize()),
+            uv: None,
+        }
+    }
+}
+
+impl Shape for Cylinder {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let (a, b, c) = self.coefficients(ray);
+        abc(a, b, c).map(|t| self.hit(ray, t))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let (a, b, c) = self.coefficients(ray);
+        Some(quadric_spans(a, b, c, |t| self.hit(ray, t)))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // Infinitely long in the z-direction
+        None
+    }
+}
+
+/// A flat round disk facing any direction
+pub struct Disk {
+    center: Point3<f32>,
+    normal: Vector3<f32>,
+    radius: f32,
+}
+
+impl Disk {
+    pub fn new(center: Point3<f32>, normal: Vector3<f32>, radius: f32) -> Disk {
+        Disk {
+            center,
+            normal: normal.normalize(),
+            radius,
+        }
+    }
+}
+
+/// The box around a disk, which is thinner along the axes the disk faces more
+fn disk_bounds(center: Point3<f32>, normal: Vector3<f32>, radius: f32) -> Aabb {
+    let extent = |n: f32| radius * (1. - n * n).max(0.).sqrt();
+    let extent = Vector3::new(extent(normal.x), extent(normal.y), extent(normal.z));
+    Aabb::new(center - extent, center + extent)
+}
+
+impl Shape for Disk {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let denom = self.normal.dot(ray.direction);
+        if denom == 0. {
+            // Looking along the disk
+            return None;
+        }
+
+        let t = self.normal.dot(self.center - ray.origin) / denom;
+        let p = ray.origin + t * ray.direction;
+        if t <= MIN_T || (p - self.center).magnitude2() > self.radius * self.radius {
+            None
+        } else {
+            Some(Hit {
+                t,
+                normal: Some(self.normal),
+                uv: None,
+            })
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(disk_bounds(self.center, self.normal, self.radius))
+    }
+}
+
+/// A cone cut off at both ends, between two end points with a radius at each. A radius of zero
+/// gives a sharp tip, and equal radii give a cylinder.
+pub struct Cone {
+    start: Point3<f32>,
+    end: Point3<f32>,
+    start_radius: f32,
+    end_radius: f32,
+    /// Whether the ends are closed with disks. An open cone has no inside.
+    capped: bool,
+    /// The direction from the start to the end
+    axis: Vector3<f32>,
+    height: f32,
+}
+
+impl Cone {
+    pub fn new(
+        start: Point3<f32>,
+        end: Point3<f32>,
+        start_radius: f32,
+        end_radius: f32,
+        capped: bool,
+    ) -> Cone {
+        let height = (end - start).magnitude();
+        Cone {
+            start,
+            end,
+            start_radius: start_radius.abs(),
+            end_radius: end_radius.abs(),
+            capped,
+            axis: (end - start) / height,
+            height,
+        }
+    }
+
+    /// How fast the radius grows along the axis
+    fn slope(&self) -> f32 {
+        (self.end_radius - self.start_radius) / self.height
+    }
+
+    /// The coefficients of |p - axis|^2 - radius^2 along the ray, where the radius grows
+    /// along the axis, so the cone goes on forever past the ends
+    fn coefficients(&self, ray: &rays::Ray) -> (f32, f32, f32) {
+        let origin = ray.origin - self.start;
+        let along_origin = origin.dot(self.axis);
+        let along_direction = ray.direction.dot(self.axis);
+
+        // The parts of the ray across the axis, and the radius along the ray
+        let radial_origin = origin - along_origin * self.axis;
+        let radial_direction = ray.direction - along_direction * self.axis;
+        let k = self.slope();
+        let m0 = self.start_radius + k * along_origin;
+        let m1 = k * along_direction;
+
+        let a = radial_direction.magnitude2() - m1 * m1;
+        let b = 2. * (radial_origin.dot(radial_direction) - m0 * m1);
+        let c = radial_origin.magnitude2() - m0 * m0;
+        (a, b, c)
+    }
+
+    /// How far along the axis a point is, from 0 at the start to the height at the end
+    fn along(&self, p: Point3<f32>) -> f32 {
+        (p - self.start).dot(self.axis)
+    }
+
+    /// The hit on the sloped side of the cone
+    fn side_hit(&self, ray: &rays::Ray, t: f32) -> Hit {
+        // The gradient of |p - axis|^2 - radius^2
+        let p = ray.origin + t * ray.direction;
+        let along = self.along(p);
+        let radial = (p - self.start) - along * self.axis;
+        let radius = self.start_radius + self.slope() * along;
+        let normal = radial - radius * self.slope() * self.axis;
+
+        Hit {
+            t,
+            normal: Some(if normal.magnitude2() > 0. {
+                normal.normalize()
+            } else {
+                // Right at the tip
+                self.axis
+            }),
+            uv: None,
+        }
+    }
+
+    /// The hit on the end cap at the start or the end, facing away from the cone
+    fn cap_hit(&self, t: f32, at_end: bool) -> Hit {
+        Hit {
+            t,
+            normal: Some(if at_end { self.axis } else { -self.axis }),
+            uv: None,
+        }
+    }
+
+    /// Where the ray crosses the planes of the ends, as (t, at the end), the nearest first.
+    /// `None` if the ray runs parallel to them.
+    fn caps(&self, ray: &rays::Ray) -> Option<[(f32, bool); 2]> {
+        let along_direction = ray.direction.dot(self.axis);
+        if along_direction == 0. {
+            return None;
+        }
+        let along_origin = self.along(ray.origin);
+        let start = (-along_origin / along_direction, false);
+        let end = ((self.height - along_origin) / along_direction, true);
+        Some(if start.0 < end.0 {
+            [start, end]
+        } else {
+            [end, start]
+        })
+    }
+}
+
+impl Shape for Cone {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let on_side =
+            |t: f32| (0. ..=self.height).contains(&self.along(ray.origin + t * ray.direction));
+        let (a, b, c) = self.coefficients(ray);
+        let sides = both_roots(a, b, c)
+            .map_or(Vec::new(), |(t1, t2)| vec![t1, t2])
+            .into_iter()
+            .filter(|&t| t > MIN_T && on_side(t))
+            .map(|t| self.side_hit(ray, t));
+
+        let caps = self
+            .caps(ray)
+            .filter(|_| self.capped)
+            .into_iter()
+            .flatten()
+            .filter(|&(t, at_end)| {
+                let radius = if at_end {
+                    self.end_radius
+                } else {
+                    self.start_radius
+                };
+                let p = ray.origin + t * ray.direction;
+                let center = if at_end { self.end } else { self.start };
+                t > MIN_T && (p - center).magnitude2() <= radius * radius
+            })
+            .map(|(t, at_end)| self.cap_hit(t, at_end));
+
+        sides
+            .chain(caps)
+            .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(Ordering::Equal))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let start = disk_bounds(self.start, self.axis, self.start_radius);
+        Some(start.union(&disk_bounds(self.end, self.axis, self.end_radius)))
+    }
+
+    /// Only a capped cone has an inside, which is the inside of the endless cone between the
+    /// planes of the ends
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        if !self.capped {
+            return None;
+        }
+
+        let (a, b, c) = self.coefficients(ray);
+        let spans = quadric_spans(a, b, c, |t| self.side_hit(ray, t));
+        let [near, far] = match self.caps(ray) {
+            Some(caps) => caps,
+            // Parallel to the ends, so either always or never between them
+            None if (0. ..=self.height).contains(&self.along(ray.origin)) => return Some(spans),
+            None => return Some(Vec::new()),
+        };
+
+        Some(
+            spans
+                .into_iter()
+                .filter(|span| span.enter.t < far.0 && span.exit.t > near.0)
+                .map(|span| Span {
+                    enter: if span.enter.t < near.0 {
+                        self.cap_hit(near.0, near.1)
+                    } else {
+                        span.enter
+                    },
+                    exit: if span.exit.t > far.0 {
+                        self.cap_hit(far.0, far.1)
+                    } else {
+                        span.exit
+                    },
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A cylinder between two end points, unlike `Cylinder` which goes on forever
+pub struct FiniteCylinder {
+    cone: Cone,
+}
+
+impl FiniteCylinder {
+    /// With `capped` the ends are closed with disks, otherwise it is an open pipe
+    pub fn new(start: Point3<f32>, end: Point3<f32>, radius: f32, capped: bool) -> FiniteCylinder {
+        FiniteCylinder {
+            cone: Cone::new(start, end, radius, radius, capped),
+        }
+    }
+}
+
+impl Shape for FiniteCylinder {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        self.cone.intersection(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.cone.bounds()
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        self.cone.intervals(ray)
+    }
+}
+
+/// One divided by each component of a direction, for the slab method
+fn inverse(direction: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(1. / direction.x, 1. / direction.y, 1. / direction.z)
+}
+
+/// The normal of the face of a box around the origin nearest to a point on its surface
+fn face_normal(half_size: Vector3<f32>, p: Vector3<f32>) -> Vector3<f32> {
+    let axis = (0..3)
+        .min_by(|&a, &b| {
+            let a = half_size[a] - p[a].abs();
+            let b = half_size[b] - p[b].abs();
+            a.abs().partial_cmp(&b.abs()).unwrap_or(Ordering::Equal)
+        })
+        .unwrap_or(0);
+    let mut normal = Vector3::zero();
+    normal[axis] = p[axis].signum();
+    normal
+}
+
+/// The hit where the ray enters or leaves a box around the origin, whichever is first in front
+/// of the ray. The ray has to be in the space of the box.
+fn box_hit(half_size: Vector3<f32>, ray: &rays::Ray) -> Option<Hit> {
+    let (near, far) = Aabb::new(Point3::from_vec(-half_size), Point3::from_vec(half_size))
+        .slab(ray.origin, inverse(ray.direction))?;
+    let t = if near > MIN_T { near } else { far };
+    if t <= MIN_T {
+        return None;
+    }
+
+    Some(Hit {
+        t,
+        normal: Some(face_normal(
+            half_size,
+            (ray.origin + t * ray.direction).to_vec(),
+        )),
+        uv: None,
+    })
+}
+
+/// Where a ray is inside a box around the origin. The ray has to be in the space of the box.
+fn box_spans(half_size: Vector3<f32>, ray: &rays::Ray) -> Vec<Span> {
+    let bounds = Aabb::new(Point3::from_vec(-half_size), Point3::from_vec(half_size));
+    let hit = |t: f32| Hit {
+        t,
+        normal: Some(face_normal(
+            half_size,
+            (ray.origin + t * ray.direction).to_vec(),
+        )),
+        uv: None,
+    };
+    match bounds.slab(ray.origin, inverse(ray.direction)) {
+        Some((near, far)) => vec![Span {
+            enter: hit(near),
+            exit: hit(far),
+        }],
+        None => Vec::new(),
+    }
+}
+
+/// A box with its sides along the axes
+pub struct AxisBox {
+    center: Point3<f32>,
+    half_size: Vector3<f32>,
+}
+
+impl AxisBox {
+    /// The box between two opposite corners, in any order
+    pub fn new(a: Point3<f32>, b: Point3<f32>) -> AxisBox {
+        AxisBox::from(Aabb::new(a, b))
+    }
+
+    /// The box as bounds
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.center - self.half_size, self.center + self.half_size)
+    }
+
+    /// The ray moved so the box is around the origin
+    fn local(&self, ray: &rays::Ray) -> rays::Ray {
+        rays::Ray {
+            origin: ray.origin - self.center.to_vec(),
+            direction: ray.direction,
+        }
+    }
+}
+
+impl From<Aabb> for AxisBox {
+    fn from(aabb: Aabb) -> AxisBox {
+        AxisBox {
+            center: aabb.centroid(),
+            half_size: aabb.size() / 2.,
+        }
+    }
+}
+
+impl Shape for AxisBox {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        box_hit(self.half_size, &self.local(ray))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        Some(box_spans(self.half_size, &self.local(ray)))
+    }
+}
+
+/// A box turned in any direction
+pub struct OrientedBox {
+    center: Point3<f32>,
+    half_size: Vector3<f32>,
+    /// The directions of the sides of the box, as the columns
+    rotation: Matrix3<f32>,
+}
+
+impl OrientedBox {
+    /// The rotation turns the box around its center, and has to be a pure rotation.
+    /// The sign of the size does not matter, like the order of the corners of an `AxisBox`.
+    pub fn new(center: Point3<f32>, size: Vector3<f32>, rotation: Matrix3<f32>) -> OrientedBox {
+        OrientedBox {
+            center,
+            half_size: size.map(f32::abs) / 2.,
+            rotation,
+        }
+    }
+
+    /// The ray in the space of the box, where it is along the axes around the origin. The
+    /// rotation keeps the length of the direction, so the distances along the ray are the same.
+    fn local(&self, ray: &rays::Ray) -> rays::Ray {
+        let inverse = self.rotation.transpose();
+        rays::Ray {
+            origin: Point3::from_vec(inverse * (ray.origin - self.center)),
+            direction: inverse * ray.direction,
+        }
+    }
+
+    fn to_world(&self, hit: Hit) -> Hit {
+        Hit {
+            normal: hit.normal.map(|normal| self.rotation * normal),
+            ..hit
+        }
+    }
+}
+
+impl Shape for OrientedBox {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        box_hit(self.half_size, &self.local(ray)).map(|hit| self.to_world(hit))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let local = Aabb::new(
+            Point3::from_vec(-self.half_size),
+            Point3::from_vec(self.half_size),
+        );
+        Some(Aabb::from_points(
+            local
+                .corners()
+                .iter()
+                .map(|corner| self.center + self.rotation * corner.to_vec()),
+        ))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let spans = box_spans(self.half_size, &self.local(ray));
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Span {
+                    enter: self.to_world(span.enter),
+                    exit: self.to_world(span.exit),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A shape inside a simpler one, usually a box, which rays are tested against first. Rays that
+/// miss the bounds never reach the shape, which saves time for shapes that are slow to hit.
+pub struct Bounded<B: Shape, S: Shape> {
+    bounds: B,
+    shape: S,
+}
+
+impl<B: Shape, S: Shape> Bounded<B, S> {
+    /// The bounds have to contain the whole shape, and have an inside
+    pub fn new(bounds: B, shape: S) -> Bounded<B, S> {
+        Bounded { bounds, shape }
+    }
+
+    /// Returns true if the ray goes through the bounds in front of its origin
+    fn inside(&self, ray: &rays::Ray) -> bool {
+        match self.bounds.intervals(ray) {
+            Some(spans) => spans.iter().any(|span| span.exit.t > MIN_T),
+            None => true,
+        }
+    }
+}
+
+impl<B: Shape, S: Shape> Shape for Bounded<B, S> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        if self.inside(ray) {
+            self.shape.intersection(ray)
+        } else {
+            None
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match (self.bounds.bounds(), self.shape.bounds()) {
+            (Some(a), Some(b)) => Some(a.intersection(&b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        if self.inside(ray) {
+            self.shape.intervals(ray)
+        } else {
+            Some(Vec::new())
         }
     }
 }
@@ -169,12 +1015,13 @@ impl Torus {
     }
 }
 
-impl Shape for Torus {
-    fn intersection(&self, ray: &rays::Ray) -> Option<Material> {
+impl Torus {
+    /// Every distance along the ray where it crosses the surface, sorted
+    fn roots(&self, ray: &rays::Ray) -> Vec<f32> {
         let sq = |x| -> f32 { x * x };
 
-        let Rsq = sq(self.inner_radius);
-        let rsq = sq(self.tube_radius);
+        let r_sq = sq(self.inner_radius);
+        let tube_sq = sq(self.tube_radius);
 
         let origin = ray.origin - self.origin;
 
@@ -184,59 +1031,64 @@ impl Shape for Torus {
                 + origin.y * ray.direction.y
                 + origin.z * ray.direction.z);
         let c11 = origin.magnitude2();
-        let c12 = Rsq - rsq;
+        let c12 = r_sq - tube_sq;
 
         let a = sq(a1);
         let b = 2. * a1 * b1;
-        let c = 2. * a1 * (c11 + c12) + sq(b1) - 4. * Rsq * (c11 - sq(origin.z));
-        let d = 2. * b1 * (c11 + c12) - 4. * Rsq * (b1 - 2. * origin.z * ray.direction.z);
-        let e = sq(c11 + c12) - 4. * Rsq * (c11 - sq(origin.z));
-
-        let t = match find_roots_quartic(a, b, c, d, e) {
-            Roots::Four(roots) => {
-                let mut min_root = roots[0];
-                if roots[1] < min_root && roots[1] > MIN_T {
-                    min_root = roots[1];
-                }
-                if roots[2] < min_root && roots[2] > MIN_T {
-                    min_root = roots[2];
-                }
-                if roots[3] < min_root && roots[3] > MIN_T {
-                    min_root = roots[3];
-                }
+        let c = 2. * a1 * (c11 + c12) + sq(b1) - 4. * r_sq * (a1 - sq(ray.direction.z));
+        let d = 2. * b1 * (c11 + c12) - 4. * r_sq * (b1 - 2. * origin.z * ray.direction.z);
+        let e = sq(c11 + c12) - 4. * r_sq * (c11 - sq(origin.z));
 
-                min_root
-            }
-            Roots::Three(roots) => {
-                let mut min_root = roots[0];
-                if roots[1] < min_root && roots[1] > MIN_T {
-                    min_root = roots[1];
-                }
-                if roots[2] < min_root && roots[2] > MIN_T {
-                    min_root = roots[2];
-                }
+        let mut roots = find_roots_quartic(a, b, c, d, e).as_ref().to_vec();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        roots
+    }
 
-                min_root
-            }
-            Roots::Two(roots) => {
-                let mut min_root = roots[0];
-                if roots[1] < min_root && roots[1] > MIN_T {
-                    min_root = roots[1];
-                }
+    fn hit(&self, ray: &rays::Ray, t: f32) -> Hit {
+        let r_sq = self.inner_radius * self.inner_radius;
+        let c12 = r_sq - self.tube_radius * self.tube_radius;
 
-                min_root
-            }
-            Roots::One(roots) => roots[0],
-            _ => -1.,
-        };
+        // The gradient of (|p|^2 + R^2 - r^2)^2 - 4R^2(x^2 + y^2)
+        let p = ray.origin - self.origin + t * ray.direction;
+        let k = p.magnitude2() + c12;
+        let normal =
+            Vector3::new(p.x * (k - 2. * r_sq), p.y * (k - 2. * r_sq), p.z * k).normalize();
 
-        if t < 0. {
-            None
-        } else {
-            Some(Material { t, normal: None })
+        Hit {
+            t,
+            normal: Some(normal),
+            uv: None,
         }
     }
 }
+
+impl Shape for Torus {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let t = self.roots(ray).into_iter().find(|&t| t > MIN_T)?;
+        Some(self.hit(ray, t))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let r_sq = self.inner_radius * self.inner_radius;
+        let c12 = r_sq - self.tube_radius * self.tube_radius;
+        let inside = |t: f32| {
+            // (|p|^2 + R^2 - r^2)^2 - 4R^2(x^2 + y^2) is negative inside the tube
+            let p = ray.origin - self.origin + t * ray.direction;
+            let k = p.magnitude2() + c12;
+            k * k - 4. * r_sq * (p.x * p.x + p.y * p.y) < 0.
+        };
+        Some(spans_between(&self.roots(ray), inside, |t| {
+            self.hit(ray, t)
+        }))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let xy = self.inner_radius.abs() + self.tube_radius.abs();
+        let z = self.tube_radius.abs();
+        let extent = Vector3::new(xy, xy, z);
+        Some(Aabb::new(self.origin - extent, self.origin + extent))
+    }
+}
 // }}}
 
 /// The plane is a flat 3-dimensional surface.
@@ -258,7 +1110,7 @@ impl Plane {
 }
 
 impl Shape for Plane {
-    fn intersection(&self, ray: &rays::Ray) -> Option<Material> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
         let origin = ray.origin - self.origin;
         let denom = self.normal.x * ray.direction.x
             + self.normal.y * ray.direction.y
@@ -269,19 +1121,36 @@ impl Shape for Plane {
             None
         } else {
             let t =
-                -(self.normal.x * origin.x + self.normal.y * origin.y + self.normal.x * origin.y)
+                -(self.normal.x * origin.x + self.normal.y * origin.y + self.normal.z * origin.z)
                     / denom;
             if t <= MIN_T {
                 // plane is behind
                 None
             } else {
-                Some(Material {
+                Some(Hit {
                     t,
                     normal: Some(self.normal),
+                    uv: None,
                 })
             }
         }
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // Infinitely large in every direction along the plane
+        None
+    }
+
+    /// The inside of a plane is the half of space behind it, opposite the normal
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let b = self.normal.dot(ray.direction);
+        let c = self.normal.dot(ray.origin - self.origin);
+        Some(quadric_spans(0., b, c, |t| Hit {
+            t,
+            normal: Some(self.normal),
+            uv: None,
+        }))
+    }
 }
 
 /// The hyperboloid is a shape that looks like two cones stuck together.
@@ -312,8 +1181,9 @@ impl Hyperboloid {
     }
 }
 
-impl Shape for Hyperboloid {
-    fn intersection(&self, ray: &rays::Ray) -> Option<Material> {
+impl Hyperboloid {
+    /// The ray in the squished space, and the coefficients of x^2 + y^2 - z^2 - lambda along it
+    fn coefficients(&self, ray: &rays::Ray) -> (Vector3<f32>, Vector3<f32>, f32, f32, f32) {
         let square = |num: f32| -> f32 { num * num };
 
         let mut origin = ray.origin - self.origin;
@@ -329,14 +1199,47 @@ impl Shape for Hyperboloid {
         let b =
             2. * origin.x * direction.x + 2. * origin.y * direction.y - 2. * origin.z * direction.z;
         let c = square(origin.x) + square(origin.y) - square(origin.z) - self.lambda;
+        (origin, direction, a, b, c)
+    }
+
+    /// The hit at a point in the squished space
+    fn hit(&self, t: f32, p: Vector3<f32>) -> Hit {
+        // The gradient of x^2 + y^2 - z^2 - lambda, in world space
+        let normal = Vector3::new(
+            p.x / self.dimensions.x,
+            p.y / self.dimensions.y,
+            -p.z / self.dimensions.z,
+        )
+        .normalize();
 
-        match abc(a, b, c) {
-            Some(t) => Some(Material { t, normal: None }),
-            None => None,
+        Hit {
+            t,
+            normal: Some(normal),
+            uv: None,
         }
     }
 }
 
+impl Shape for Hyperboloid {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let (origin, direction, a, b, c) = self.coefficients(ray);
+        abc(a, b, c).map(|t| self.hit(t, origin + t * direction))
+    }
+
+    /// The inside is around the z-axis, where x^2 + y^2 - z^2 is less than lambda
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let (origin, direction, a, b, c) = self.coefficients(ray);
+        Some(quadric_spans(a, b, c, |t| {
+            self.hit(t, origin + t * direction)
+        }))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // Both the sheets keep growing along the z-axis
+        None
+    }
+}
+
 /// A spheriod is to a sphere what a square is to a rectangle.
 /// Dimensions where all axis equal each other gives a sphere.
 pub struct Spheroid {
@@ -359,8 +1262,9 @@ impl Spheroid {
     }
 }
 
-impl Shape for Spheroid {
-    fn intersection(&self, ray: &rays::Ray) -> Option<Material> {
+impl Spheroid {
+    /// The ray in the squished space, and the coefficients of x^2 + y^2 + z^2 - r^2 along it
+    fn coefficients(&self, ray: &rays::Ray) -> (Vector3<f32>, Vector3<f32>, f32, f32, f32) {
         let mut origin = ray.origin - self.origin;
         origin.x /= self.dimensions.x;
         origin.y /= self.dimensions.y;
@@ -374,13 +1278,316 @@ impl Shape for Spheroid {
         let b =
             2. * origin.x * direction.x + 2. * origin.y * direction.y + 2. * origin.z * direction.z;
         let c = origin.magnitude2() - self.radius * self.radius;
+        (origin, direction, a, b, c)
+    }
 
-        match abc(a, b, c) {
-            Some(t) => Some(Material {
-                t,
-                normal: Some((origin + t * direction).normalize()),
-            }),
-            None => None,
+    /// The hit at a point in the squished space
+    fn hit(&self, t: f32, p: Vector3<f32>) -> Hit {
+        // The gradient of x^2 + y^2 + z^2 - r^2, in world space
+        let normal = Vector3::new(
+            p.x / self.dimensions.x,
+            p.y / self.dimensions.y,
+            p.z / self.dimensions.z,
+        )
+        .normalize();
+
+        Hit {
+            t,
+            normal: Some(normal),
+            uv: None,
+        }
+    }
+}
+
+impl Shape for Spheroid {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let (origin, direction, a, b, c) = self.coefficients(ray);
+        abc(a, b, c).map(|t| self.hit(t, origin + t * direction))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let (origin, direction, a, b, c) = self.coefficients(ray);
+        Some(quadric_spans(a, b, c, |t| {
+            self.hit(t, origin + t * direction)
+        }))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // The surface is where |(p - origin) / dimensions| = radius
+        let extent = self.radius.abs() * self.dimensions;
+        Some(Aabb::new(self.origin - extent, self.origin + extent))
+    }
+}
+
+/// Any surface where a polynomial of degree two in x, y and z is zero, like ellipsoids,
+/// paraboloids, hyperboloids and cones. The inside is where the polynomial is negative.
+pub struct Quadric {
+    /// The symmetric matrix Q, where the surface is the points p = (x, y, z, 1) with p^T Q p = 0
+    matrix: Matrix4<f32>,
+    bounds: Option<Aabb>,
+}
+
+impl Quadric {
+    /// The surface ax^2 + by^2 + cz^2 + 2dxy + 2exz + 2fyz + 2gx + 2hy + 2iz + j = 0, with the
+    /// coefficients in the order a to j
+    pub fn new(coefficients: [f32; 10]) -> Quadric {
+        let [a, b, c, d, e, f, g, h, i, j] = coefficients;
+        Quadric::from_matrix(Matrix4::new(a, d, e, g, d, b, f, h, e, f, c, i, g, h, i, j))
+    }
+
+    /// The surface p^T Q p = 0 for points p = (x, y, z, 1). Only the symmetric part of the
+    /// matrix matters, so it is made symmetric.
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Quadric {
+        let matrix = (matrix + matrix.transpose()) / 2.;
+        Quadric {
+            matrix,
+            bounds: ellipsoid_bounds(matrix),
+        }
+    }
+
+    /// (x/a)^2 + (y/b)^2 + (z/c)^2 = 1, with the radii a, b and c
+    pub fn ellipsoid(center: Point3<f32>, radii: Vector3<f32>) -> Quadric {
+        let [a, b, c] = inverse_squares(radii);
+        Quadric::new([a, b, c, 0., 0., 0., 0., 0., 0., -1.]).translate(center)
+    }
+
+    /// (x/a)^2 + (y/b)^2 = z/c, a bowl opening along the z-axis from the vertex
+    pub fn paraboloid(vertex: Point3<f32>, radii: Vector3<f32>) -> Quadric {
+        let [a, b, _] = inverse_squares(radii);
+        Quadric::new([a, b, 0., 0., 0., 0., 0., 0., -0.5 / radii.z, 0.]).translate(vertex)
+    }
+
+    /// (x/a)^2 + (y/b)^2 - (z/c)^2 = 1, a single tube along the z-axis that is narrowest at the
+    /// center
+    pub fn hyperboloid_one_sheet(center: Point3<f32>, radii: Vector3<f32>) -> Quadric {
+        let [a, b, c] = inverse_squares(radii);
+        Quadric::new([a, b, -c, 0., 0., 0., 0., 0., 0., -1.]).translate(center)
+    }
+
+    /// (z/c)^2 - (x/a)^2 - (y/b)^2 = 1, two bowls facing away from each other along the z-axis
+    pub fn hyperboloid_two_sheets(center: Point3<f32>, radii: Vector3<f32>) -> Quadric {
+        let [a, b, c] = inverse_squares(radii);
+        Quadric::new([a, b, -c, 0., 0., 0., 0., 0., 0., 1.]).translate(center)
+    }
+
+    /// (x/a)^2 + (y/b)^2 = (z/c)^2, two cones along the z-axis meeting at the apex
+    pub fn cone(apex: Point3<f32>, radii: Vector3<f32>) -> Quadric {
+        let [a, b, c] = inverse_squares(radii);
+        Quadric::new([a, b, -c, 0., 0., 0., 0., 0., 0., 0.]).translate(apex)
+    }
+
+    /// The same surface moved so the origin ends up at a point
+    fn translate(self, offset: Point3<f32>) -> Quadric {
+        let inverse = Matrix4::from_translation(-offset.to_vec());
+        Quadric::from_matrix(inverse.transpose() * self.matrix * inverse)
+    }
+
+    /// The coefficients of p^T Q p along the ray
+    fn coefficients(&self, ray: &rays::Ray) -> (f32, f32, f32) {
+        let origin = ray.origin.to_homogeneous();
+        let direction = ray.direction.extend(0.);
+        let q_origin = self.matrix * origin;
+        let q_direction = self.matrix * direction;
+        (
+            direction.dot(q_direction),
+            2. * direction.dot(q_origin),
+            origin.dot(q_origin),
+        )
+    }
+
+    fn hit(&self, ray: &rays::Ray, t: f32) -> Hit {
+        // The gradient of p^T Q p is 2Qp, and has no direction at the apex of a cone
+        let p = ray.origin + t * ray.direction;
+        let gradient = (self.matrix * p.to_homogeneous()).truncate();
+        Hit {
+            t,
+            normal: if gradient.magnitude2() > 0. {
+                Some(gradient.normalize())
+            } else {
+                None
+            },
+            uv: None,
+        }
+    }
+}
+
+/// One divided by the square of each radius
+fn inverse_squares(radii: Vector3<f32>) -> [f32; 3] {
+    [
+        1. / (radii.x * radii.x),
+        1. / (radii.y * radii.y),
+        1. / (radii.z * radii.z),
+    ]
+}
+
+/// The box around a quadric if it is an ellipsoid, and `None` for every other kind
+fn ellipsoid_bounds(matrix: Matrix4<f32>) -> Option<Aabb> {
+    // Only ellipsoids have a quadratic part that is positive or negative in every direction
+    let m = Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    );
+    let minor = m.x.x * m.y.y - m.x.y * m.y.x;
+    let definite = (m.x.x > 0. && minor > 0. && m.determinant() > 0.)
+        || (m.x.x < 0. && minor > 0. && m.determinant() < 0.);
+    if !definite {
+        return None;
+    }
+
+    // The planes x = k touching the surface are the planes (1, 0, 0, -k) on the dual quadric,
+    // which is given by the inverse matrix
+    let dual = matrix.invert()?;
+    let mut min = Point3::origin();
+    let mut max = Point3::origin();
+    for axis in 0..3 {
+        let (k1, k2) = both_roots(dual.w.w, -2. * dual[axis][3], dual[axis][axis])?;
+        min[axis] = k1;
+        max[axis] = k2;
+    }
+    Some(Aabb::new(min, max))
+}
+
+impl Shape for Quadric {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        // The spans handle the rays that only cross the surface once or not at all, like along
+        // the axis of a paraboloid or parallel to the side of a cone
+        self.intervals(ray)?
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|hit| hit.t > MIN_T)
+            .filter(|hit| hit.t.is_finite())
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let (a, b, c) = self.coefficients(ray);
+        Some(quadric_spans(a, b, c, |t| self.hit(ray, t)))
+    }
+}
+
+/// A superellipsoid, which can be anything from a box to an ellipsoid to an octahedron and
+/// beyond, depending on its two exponents. Its surface has no closed form solution along a ray,
+/// so the hits are found numerically.
+pub struct Superquadric {
+    center: Point3<f32>,
+    radii: Vector3<f32>,
+    /// The roundness along the z-axis
+    vertical: f32,
+    /// The roundness across the z-axis
+    horizontal: f32,
+}
+
+impl Superquadric {
+    /// Exponents of 1 give an ellipsoid, smaller ones make it more like a box, 2 makes it an
+    /// octahedron, and larger ones pinch it further. Exponents too close to 0 make the shape so
+    /// sharp that the hits are found badly.
+    pub fn new(
+        center: Point3<f32>,
+        radii: Vector3<f32>,
+        vertical: f32,
+        horizontal: f32,
+    ) -> Superquadric {
+        Superquadric {
+            center,
+            radii,
+            vertical,
+            horizontal,
+        }
+    }
+
+    /// (|x|^(2/h) + |y|^(2/h))^(h/v) + |z|^(2/v) - 1, which is negative inside the shape, for a
+    /// point in the space where the shape fills the cube from -1 to 1
+    fn inside_outside(&self, p: Vector3<f32>) -> f32 {
+        let across = p.x.abs().powf(2. / self.horizontal) + p.y.abs().powf(2. / self.horizontal);
+        across.powf(self.horizontal / self.vertical) + p.z.abs().powf(2. / self.vertical) - 1.
+    }
+
+    /// The ray in the space where the shape fills the cube from -1 to 1. The distances along the
+    /// ray stay the same.
+    fn local(&self, ray: &rays::Ray) -> (Vector3<f32>, Vector3<f32>) {
+        let scale = |v: Vector3<f32>| {
+            Vector3::new(v.x / self.radii.x, v.y / self.radii.y, v.z / self.radii.z)
+        };
+        (scale(ray.origin - self.center), scale(ray.direction))
+    }
+
+    /// Every distance along the ray where it crosses the surface, sorted. The part of the ray
+    /// inside the bounds is split into steps, and a root is searched for in every step where the
+    /// inside-outside function changes sign, so a ray only grazing the surface can be missed.
+    fn roots(&self, ray: &rays::Ray) -> Vec<f32> {
+        const STEPS: usize = 64;
+
+        let (origin, direction) = self.local(ray);
+        let cube = Aabb::new(Point3::new(-1., -1., -1.), Point3::new(1., 1., 1.));
+        let (near, far) = match cube.slab(Point3::from_vec(origin), inverse(direction)) {
+            Some(slab) => slab,
+            None => return Vec::new(),
+        };
+
+        let f = |t: f32| self.inside_outside(origin + t * direction);
+        let step = (far - near) / STEPS as f32;
+        let mut roots = Vec::new();
+        let (mut t0, mut f0) = (near, f(near));
+        for i in 1..=STEPS {
+            let t1 = near + step * i as f32;
+            let f1 = f(t1);
+            if (f0 < 0.) != (f1 < 0.) {
+                let mut convergency = SimpleConvergency {
+                    eps: 1e-5 * (1. + t1.abs()),
+                    max_iter: 50,
+                };
+                roots.push(find_root_brent(t0, t1, f, &mut convergency).unwrap_or((t0 + t1) / 2.));
+            }
+            t0 = t1;
+            f0 = f1;
         }
+        roots
+    }
+
+    fn hit(&self, ray: &rays::Ray, t: f32) -> Hit {
+        // The gradient of the inside-outside function, scaled back to world space
+        let (origin, direction) = self.local(ray);
+        let p = origin + t * direction;
+        let h = 1e-3;
+        let d = |offset: Vector3<f32>| {
+            self.inside_outside(p + offset) - self.inside_outside(p - offset)
+        };
+        let gradient = Vector3::new(
+            d(Vector3::new(h, 0., 0.)) / self.radii.x,
+            d(Vector3::new(0., h, 0.)) / self.radii.y,
+            d(Vector3::new(0., 0., h)) / self.radii.z,
+        );
+
+        Hit {
+            t,
+            normal: Some(gradient.normalize()),
+            uv: None,
+        }
+    }
+}
+
+impl Shape for Superquadric {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let t = self.roots(ray).into_iter().find(|&t| t > MIN_T)?;
+        Some(self.hit(ray, t))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            self.center - self.radii,
+            self.center + self.radii,
+        ))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let (origin, direction) = self.local(ray);
+        let inside = |t: f32| self.inside_outside(origin + t * direction) < 0.;
+        Some(spans_between(&self.roots(ray), inside, |t| {
+            self.hit(ray, t)
+        }))
     }
 }
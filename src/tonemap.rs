@@ -0,0 +1,106 @@
+//! Turning the unlimited linear colors of the film into colors a screen can show
+
+extern crate cgmath;
+
+use super::render::Rgb;
+use cgmath::*;
+
+/// How colors brighter than 1 are squeezed into what a screen can show
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    /// Cuts off everything brighter than 1
+    Clamp,
+    /// c / (1 + c), which never quite reaches white
+    Reinhard,
+    /// A fit of the ACES filmic curve, with more contrast and soft highlights
+    Aces,
+}
+
+impl Operator {
+    /// The next operator, for switching between them
+    pub fn next(self) -> Operator {
+        match self {
+            Operator::Clamp => Operator::Reinhard,
+            Operator::Reinhard => Operator::Aces,
+            Operator::Aces => Operator::Clamp,
+        }
+    }
+
+    /// Maps a linear channel of any brightness to between 0 and 1
+    pub fn apply(self, c: f32) -> f32 {
+        let c = c.max(0.);
+        match self {
+            Operator::Clamp => c.min(1.),
+            Operator::Reinhard => c / (1. + c),
+            Operator::Aces => {
+                // Krzysztof Narkowicz's fit, which expects the exposure to be a little lower
+                let c = c * 0.6;
+                ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0., 1.)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Operator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Operator, String> {
+        match name {
+            "clamp" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "aces" => Ok(Operator::Aces),
+            _ => Err(format!("Unknown tone mapping operator {}", name)),
+        }
+    }
+}
+
+/// Encodes a linear channel between 0 and 1 with the sRGB transfer function that screens and
+/// image files expect
+pub fn srgb_encode(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Everything that happens to a color between the film and the screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMap {
+    /// How much brighter the image is made, in stops, so 1 doubles the brightness
+    pub exposure: f32,
+    pub operator: Operator,
+    /// Whether to encode the colors as sRGB. Turning it off gives linear values.
+    pub srgb: bool,
+}
+
+impl Default for ToneMap {
+    /// No change in exposure, clamping and sRGB encoding
+    fn default() -> ToneMap {
+        ToneMap::new(0., Operator::Clamp)
+    }
+}
+
+impl ToneMap {
+    pub fn new(exposure: f32, operator: Operator) -> ToneMap {
+        ToneMap {
+            exposure,
+            operator,
+            srgb: true,
+        }
+    }
+
+    /// A linear color of any brightness as a color for the screen, between 0 and 1
+    pub fn map(&self, color: Rgb) -> Rgb {
+        let scale = 2f32.powf(self.exposure);
+        let channel = |c: f32| {
+            let c = self.operator.apply(c * scale);
+            if self.srgb {
+                srgb_encode(c)
+            } else {
+                c
+            }
+        };
+        Vector3::new(channel(color.x), channel(color.y), channel(color.z))
+    }
+}
@@ -0,0 +1,146 @@
+//! Affine transformations of shapes, so a shape can be moved, rotated and scaled without the
+//! shape itself knowing about it
+
+extern crate cgmath;
+
+use super::aabb::Aabb;
+use super::rays;
+use super::shapes::{Hit, Shape, Span};
+use cgmath::*;
+
+/// A shape placed in the world with an affine transformation.
+///
+/// The ray is transformed into the object space of the inner shape, and the hit is transformed
+/// back into world space. Since `Shape` is implemented for references to shapes and for `Arc`, the
+/// same shape can be instanced several times with different transformations.
+pub struct Transformed<S: Shape> {
+    shape: S,
+    /// Object space to world space
+    transform: Matrix4<f32>,
+    /// World space to object space
+    inverse: Matrix4<f32>,
+    /// Transforms normals from object space to world space
+    normal_matrix: Matrix3<f32>,
+}
+
+impl<S: Shape> Transformed<S> {
+    /// Places a shape in the world with a transformation from object space to world space.
+    /// Returns `None` if the transformation is not invertible.
+    pub fn new(shape: S, transform: Matrix4<f32>) -> Option<Transformed<S>> {
+        let inverse = transform.invert()?;
+        let normal_matrix = Matrix3::from_cols(
+            inverse.x.truncate(),
+            inverse.y.truncate(),
+            inverse.z.truncate(),
+        )
+        .transpose();
+
+        Some(Transformed {
+            shape,
+            transform,
+            inverse,
+            normal_matrix,
+        })
+    }
+
+    /// A shape with no transformation
+    pub fn identity(shape: S) -> Transformed<S> {
+        Transformed {
+            shape,
+            transform: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+            normal_matrix: Matrix3::identity(),
+        }
+    }
+
+    /// Applies another transformation after the current one.
+    /// Returns `None` if the resulting transformation is not invertible.
+    pub fn then(self, transform: Matrix4<f32>) -> Option<Transformed<S>> {
+        let transform = transform * self.transform;
+        Transformed::new(self.shape, transform)
+    }
+
+    /// Moves the shape by an offset
+    pub fn translate(self, offset: Vector3<f32>) -> Transformed<S> {
+        self.then(Matrix4::from_translation(offset))
+            .expect("a translation is always invertible")
+    }
+
+    /// Rotates the shape around an axis through the world origin
+    pub fn rotate(self, axis: Vector3<f32>, angle: Rad<f32>) -> Transformed<S> {
+        self.then(Matrix4::from_axis_angle(axis.normalize(), angle))
+            .expect("a rotation is always invertible")
+    }
+
+    /// Scales the shape along each axis, relative to the world origin.
+    /// Returns `None` if any of the factors is zero.
+    pub fn scale(self, factors: Vector3<f32>) -> Option<Transformed<S>> {
+        self.then(Matrix4::from_nonuniform_scale(
+            factors.x, factors.y, factors.z,
+        ))
+    }
+
+    /// The transformation from object space to world space
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+
+    /// The shape that is transformed
+    pub fn shape(&self) -> &S {
+        &self.shape
+    }
+}
+
+impl<S: Shape> Transformed<S> {
+    /// The ray in object space, and how much longer the direction is there than in world space
+    fn object_ray(&self, ray: &rays::Ray) -> (rays::Ray, f32) {
+        let origin = Point3::from_homogeneous(self.inverse * ray.origin.to_homogeneous());
+        let direction = (self.inverse * ray.direction.extend(0.)).truncate();
+        (rays::Ray::new(origin, direction), direction.magnitude())
+    }
+
+    /// A hit in object space as a hit in world space
+    fn world_hit(&self, hit: Hit, scale: f32) -> Hit {
+        // The direction is normalized by the ray, so the distance along the ray has to be
+        // scaled back to world space
+        Hit {
+            t: hit.t / scale,
+            normal: hit
+                .normal
+                .map(|normal| (self.normal_matrix * normal).normalize()),
+            uv: hit.uv,
+        }
+    }
+}
+
+impl<S: Shape> Shape for Transformed<S> {
+    fn intersection(&self, ray: &rays::Ray) -> Option<Hit> {
+        let (ray, scale) = self.object_ray(ray);
+        let hit = self.shape.intersection(&ray)?;
+        Some(self.world_hit(hit, scale))
+    }
+
+    fn intervals(&self, ray: &rays::Ray) -> Option<Vec<Span>> {
+        let (ray, scale) = self.object_ray(ray);
+        let spans = self.shape.intervals(&ray)?;
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Span {
+                    enter: self.world_hit(span.enter, scale),
+                    exit: self.world_hit(span.exit, scale),
+                })
+                .collect(),
+        )
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let bounds = self.shape.bounds()?;
+        if bounds.is_empty() {
+            return Some(bounds);
+        }
+        Some(Aabb::from_points(bounds.corners().iter().map(|&corner| {
+            Point3::from_homogeneous(self.transform * corner.to_homogeneous())
+        })))
+    }
+}